@@ -0,0 +1,231 @@
+//! Where stores live: the [`Backend`] trait, and [`FileBackend`], the crash-safe file
+//! persistence behind every path-based constructor.
+
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, ErrorKind, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::crypto::rand_u64;
+use crate::error::{Error, Result};
+
+/// A lock held for as long as a store is open; dropping it releases the lock.
+pub type LockGuard = Box<dyn Send + Sync>;
+
+/// Storage for one store's encrypted bytes. Everything a backend sees is already sealed,
+/// so it needs no trust beyond keeping the bytes (and a stale copy is caught by
+/// [`Config::min_generation`](crate::Config::min_generation)).
+///
+/// Only the four required methods are needed for a working store. Locking and the
+/// append-only journal used by [`Config::wal`](crate::Config::wal) are optional: without
+/// them the store isn't protected from other processes, and every save is a rewrite.
+pub trait Backend: fmt::Debug + Send + Sync {
+    /// Whether there is a store to load.
+    fn exists(&self) -> Result<bool>;
+
+    /// The store's bytes, as last passed to [`Backend::store`].
+    fn load(&self) -> Result<Vec<u8>>;
+
+    /// Replace the store's bytes. Must be atomic: after a crash, [`Backend::load`] returns
+    /// either the old or the new bytes in full.
+    fn store(&self, bytes: &[u8]) -> Result<()>;
+
+    /// Delete the store, its journal, and anything else kept for it.
+    fn remove(&self) -> Result<()>;
+
+    /// Lock the store against other processes until the guard drops, failing with
+    /// [`Error::Locked`] if they hold a conflicting lock. Only called for
+    /// [`LockMode::Shared`](crate::LockMode::Shared) or `Exclusive`; the default takes no
+    /// lock.
+    fn lock(&self, exclusive: bool) -> Result<LockGuard> {
+        let _ = exclusive;
+        Ok(Box::new(()))
+    }
+
+    /// Whether this backend keeps the journal; see [`Backend::append_journal`].
+    fn supports_journal(&self) -> bool {
+        false
+    }
+
+    /// The journal's bytes, or `None` if there is none.
+    fn load_journal(&self) -> Result<Option<Vec<u8>>> {
+        Ok(None)
+    }
+
+    /// Durably write `bytes` at `offset` into the journal, first dropping anything past
+    /// `offset` (a torn earlier append). Offset `0` starts a new journal.
+    fn append_journal(&self, offset: u64, bytes: &[u8]) -> Result<()> {
+        let _ = (offset, bytes);
+        Err(Error::Io(io::Error::new(
+            ErrorKind::Unsupported,
+            "backend has no journal",
+        )))
+    }
+
+    /// Delete the journal, if there is one.
+    fn remove_journal(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// A store file, with its journal in a `<path>.wal` sidecar and its lock on a
+/// `<path>.lock` sidecar.
+#[derive(Debug, Clone)]
+pub struct FileBackend {
+    path: PathBuf,
+}
+
+impl FileBackend {
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        FileBackend {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Backend for FileBackend {
+    fn exists(&self) -> Result<bool> {
+        Ok(self.path.is_file())
+    }
+
+    fn load(&self) -> Result<Vec<u8>> {
+        Ok(fs::read(&self.path)?)
+    }
+
+    fn store(&self, bytes: &[u8]) -> Result<()> {
+        atomic_write(&self.path, bytes)
+    }
+
+    fn remove(&self) -> Result<()> {
+        remove_if_exists(&self.path)?;
+        remove_if_exists(&sidecar(&self.path, ".wal"))?;
+        let _ = fs::remove_file(sidecar(&self.path, ".lock"));
+        Ok(())
+    }
+
+    fn lock(&self, exclusive: bool) -> Result<LockGuard> {
+        let lock_path = sidecar(&self.path, ".lock");
+        if let Some(parent) = lock_path.parent() {
+            if !parent.as_os_str().is_empty() && !parent.is_dir() {
+                fs::create_dir_all(parent)?;
+            }
+        }
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&lock_path)?;
+
+        // std's native file locking (stable since 1.89). `try_lock` is the exclusive variant.
+        let result = if exclusive {
+            file.try_lock()
+        } else {
+            file.try_lock_shared()
+        };
+        result.map_err(|_| Error::Locked)?;
+        Ok(Box::new(file))
+    }
+
+    fn supports_journal(&self) -> bool {
+        true
+    }
+
+    fn load_journal(&self) -> Result<Option<Vec<u8>>> {
+        match fs::read(sidecar(&self.path, ".wal")) {
+            Ok(raw) => Ok(Some(raw)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn append_journal(&self, offset: u64, bytes: &[u8]) -> Result<()> {
+        let path = sidecar(&self.path, ".wal");
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+        file.set_len(offset)?;
+        file.seek(SeekFrom::Start(offset))?;
+        file.write_all(bytes)?;
+        file.sync_data()?;
+
+        // a fresh log is a new directory entry; make that durable too
+        if offset == 0 {
+            if let Ok(dir_file) = File::open(parent_dir(&path)) {
+                let _ = dir_file.sync_all();
+            }
+        }
+        Ok(())
+    }
+
+    fn remove_journal(&self) -> Result<()> {
+        remove_if_exists(&sidecar(&self.path, ".wal"))
+    }
+}
+
+fn sidecar(path: &Path, suffix: &str) -> PathBuf {
+    let mut s = path.as_os_str().to_os_string();
+    s.push(suffix);
+    PathBuf::from(s)
+}
+
+fn parent_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => Path::new("."),
+    }
+}
+
+fn remove_if_exists(path: &Path) -> Result<()> {
+    match fs::remove_file(path) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Write via temp file + fsync + rename + dir fsync, so a crash leaves either the old or
+/// the new complete file — never a torn one.
+fn atomic_write(path: &Path, bytes: &[u8]) -> Result<()> {
+    let dir = parent_dir(path);
+    if !dir.is_dir() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| Error::CorruptStore("store path has no file name".to_string()))?;
+    let mut tmp_name = file_name.to_os_string();
+    tmp_name.push(format!(".tmp.{}.{:016x}", std::process::id(), rand_u64()?));
+    let tmp_path = dir.join(tmp_name);
+
+    let write_result = (|| -> Result<()> {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&tmp_path)?;
+        file.write_all(bytes)?;
+        file.sync_all()?;
+        Ok(())
+    })();
+    if let Err(e) = write_result {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(e);
+    }
+
+    if let Err(e) = fs::rename(&tmp_path, path) {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(e.into());
+    }
+
+    if let Ok(dir_file) = File::open(dir) {
+        let _ = dir_file.sync_all();
+    }
+    Ok(())
+}
@@ -0,0 +1,131 @@
+//! Background threads a store can run: the expiry sweeper
+//! ([`Config::sweep_interval`](crate::Config::sweep_interval)) and the autosave timer
+//! ([`AutoSave::Periodic`] and [`AutoSave::Debounced`]).
+//!
+//! Each holds the store only weakly, upgrading for the length of one sweep or save, and
+//! stops once the last handle is dropped.
+
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Weak};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::config::{AutoSave, SweepHook};
+use crate::store::Inner;
+
+/// A background thread, stopped and joined when dropped along with the store.
+pub(crate) struct Worker {
+    // dropping it wakes the thread to stop
+    wake: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Worker {
+    /// Run `body` on a thread of its own. It's handed the store and a receiver that
+    /// [`Worker::wake`] sends to, and that disconnects when the worker is dropped.
+    fn spawn<F>(name: &str, inner: &Arc<Inner>, body: F) -> Option<Worker>
+    where
+        F: FnOnce(Weak<Inner>, Receiver<()>) + Send + 'static,
+    {
+        let store = Arc::downgrade(inner);
+        let (wake, woken) = mpsc::channel();
+        // without a thread, the store just goes without
+        let thread = thread::Builder::new()
+            .name(name.to_string())
+            .spawn(move || body(store, woken))
+            .ok()?;
+        Some(Worker {
+            wake: Some(wake),
+            thread: Some(thread),
+        })
+    }
+
+    pub(crate) fn wake(&self) {
+        if let Some(wake) = &self.wake {
+            let _ = wake.send(());
+        }
+    }
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        drop(self.wake.take());
+        if let Some(thread) = self.thread.take() {
+            // the worker itself may hold the last handle; it stops on its own then
+            if thread.thread().id() != thread::current().id() {
+                let _ = thread.join();
+            }
+        }
+    }
+}
+
+/// Sweep `inner` every `interval`, if given and the store is writable.
+pub(crate) fn start_sweeper(
+    inner: &Arc<Inner>,
+    interval: Option<Duration>,
+    on_sweep: Option<SweepHook>,
+) {
+    let Some(interval) = interval.filter(|_| inner.ensure_writable().is_ok()) else {
+        return;
+    };
+    let worker = Worker::spawn("microkv-sweeper", inner, move |store, woken| {
+        while let Err(RecvTimeoutError::Timeout) = woken.recv_timeout(interval) {
+            let Some(inner) = store.upgrade() else {
+                return;
+            };
+            let swept = inner.sweep_expired();
+            // the store may go away with this last strong reference
+            drop(inner);
+            if let Some(report) = &on_sweep {
+                if !matches!(swept, Ok(0)) {
+                    report(swept);
+                }
+            }
+        }
+    });
+    if let Some(worker) = worker {
+        let _ = inner.sweeper.set(worker);
+    }
+}
+
+/// Start the autosave timer for `autosave`, if it has one and there's somewhere to save.
+/// A failed save is left dirty, for the next one to retry.
+pub(crate) fn start_autosaver(inner: &Arc<Inner>, autosave: AutoSave) {
+    if !inner.can_save() {
+        return;
+    }
+    let worker = match autosave {
+        AutoSave::Periodic(every) => {
+            Worker::spawn("microkv-autosave", inner, move |store, woken| {
+                while let Err(RecvTimeoutError::Timeout) = woken.recv_timeout(every) {
+                    let Some(inner) = store.upgrade() else {
+                        return;
+                    };
+                    let _ = inner.save_if_dirty();
+                }
+            })
+        }
+        // woken by every write, saving once they've stopped for `quiet`
+        AutoSave::Debounced(quiet) => {
+            Worker::spawn("microkv-autosave", inner, move |store, woken| {
+                while woken.recv().is_ok() {
+                    loop {
+                        match woken.recv_timeout(quiet) {
+                            Ok(()) => continue,
+                            Err(RecvTimeoutError::Timeout) => break,
+                            Err(RecvTimeoutError::Disconnected) => return,
+                        }
+                    }
+                    let Some(inner) = store.upgrade() else {
+                        return;
+                    };
+                    let _ = inner.save_if_dirty();
+                }
+            })
+        }
+        AutoSave::Manual | AutoSave::OnEveryWrite | AutoSave::OnDrop => None,
+    };
+    if let Some(worker) = worker {
+        let _ = inner.autosaver.set(worker);
+    }
+}
@@ -0,0 +1,140 @@
+//! Portable backups: copies of a store (or of some of its namespaces) re-sealed under a
+//! credential of their own, and restoring them into another store.
+//!
+//! A bundle is an ordinary store file with fresh keys, so it can also be opened directly.
+
+use std::collections::BTreeSet;
+use std::path::Path;
+
+use crate::config::{Config, Credential, OnConflict};
+use crate::error::{Error, Result};
+use crate::store::{fetch, seal_encoded_into, MicroKV};
+
+impl MicroKV {
+    /// Write a bundle holding every live entry, sealed under `cred` rather than any of this
+    /// store's credentials. It shares no keys with the store; it keeps its cipher, padding,
+    /// name encryption and KDF, and entries keep their expiry.
+    pub fn backup_to(&self, path: impl AsRef<Path>, cred: Credential) -> Result<()> {
+        self.backup(path.as_ref(), cred, None)
+    }
+
+    /// [`MicroKV::backup_to`], copying only the given namespaces.
+    pub fn backup_namespaces_to(
+        &self,
+        path: impl AsRef<Path>,
+        cred: Credential,
+        namespaces: &[&str],
+    ) -> Result<()> {
+        self.backup(path.as_ref(), cred, Some(namespaces))
+    }
+
+    fn backup(&self, path: &Path, cred: Credential, only: Option<&[&str]>) -> Result<()> {
+        let keep = match only {
+            Some(names) => Some(
+                names
+                    .iter()
+                    .map(|ns| self.inner.ns_id(ns))
+                    .collect::<Result<BTreeSet<_>>>()?,
+            ),
+            None => None,
+        };
+        let entries = {
+            let store = self.inner.read_store()?;
+            self.inner.open_all(&store, |ns_id| {
+                keep.as_ref().is_none_or(|keep| keep.contains(ns_id))
+            })?
+        };
+
+        let namespaces: BTreeSet<&str> = entries.iter().map(|(ns, _, _)| ns.as_str()).collect();
+        let (codec, namespace_codecs) = self.inner.codecs_by_name(namespaces.iter().copied())?;
+        let config = Config {
+            kdf: self.kdf_params(),
+            cipher: self.inner.cipher()?,
+            padding: self.inner.padding_by_name(namespaces)?,
+            codec,
+            namespace_codecs,
+            encrypt_names: self.inner.encrypted_names(),
+            ..Default::default()
+        };
+        let bundle = MicroKV::in_memory_with(cred, config)?;
+        {
+            let mut store = bundle.inner.write_store()?;
+            for (ns, key, frame) in &entries {
+                seal_encoded_into(
+                    &bundle.inner,
+                    &mut store,
+                    ns,
+                    key,
+                    &frame.value,
+                    frame.expires_at,
+                )?;
+            }
+        }
+        bundle.save_as(path)
+    }
+
+    /// Import a bundle written by [`MicroKV::backup_to`], opened with its own `cred`,
+    /// returning how many entries were written. Keys that already hold a live value are
+    /// handled per `on_conflict`. The bundle is restored whole or not at all (so a
+    /// conflict under [`OnConflict::Fail`] writes nothing), and watchers hear of it only
+    /// once it has been. Values are copied as encoded, so each namespace must use the same
+    /// [`Codec`](crate::Codec) in both.
+    pub fn restore_from(
+        &self,
+        path: impl AsRef<Path>,
+        cred: Credential,
+        on_conflict: OnConflict,
+    ) -> Result<usize> {
+        self.inner.ensure_writable()?;
+        let bundle = MicroKV::open_existing_with(
+            path,
+            cred,
+            Config {
+                read_only: true,
+                ..Default::default()
+            },
+        )?;
+        let entries = {
+            let store = bundle.inner.read_store()?;
+            bundle.inner.open_all(&store, |_| true)?
+        };
+
+        // all or nothing: a conflict or failure part way leaves the store as it was
+        let restored = self.on_working_copy(|store| {
+            let mut taken = Vec::with_capacity(entries.len());
+            for (ns, key, _) in &entries {
+                let codec = bundle.inner.codec_by_name(ns)?;
+                if codec != self.inner.codec_by_name(ns)? {
+                    return Err(Error::Serialization(format!(
+                        "namespace {ns:?} is encoded as {codec:?} in the backup"
+                    )));
+                }
+                let (ns_id, key_id) = self.inner.locate(ns, key)?;
+                let live = match fetch(store, &ns_id, &key_id) {
+                    Some(entry) => self.inner.is_live(&ns_id, &key_id, &entry)?,
+                    None => false,
+                };
+                if live && on_conflict == OnConflict::Fail {
+                    return Err(Error::Conflict {
+                        namespace: ns.clone(),
+                        key: key.clone(),
+                    });
+                }
+                taken.push(live);
+            }
+            let mut restored = 0;
+            for ((ns, key, frame), live) in entries.iter().zip(taken) {
+                if live && on_conflict == OnConflict::Skip {
+                    continue;
+                }
+                seal_encoded_into(&self.inner, store, ns, key, &frame.value, frame.expires_at)?;
+                restored += 1;
+            }
+            Ok(restored)
+        })?;
+        if restored > 0 {
+            self.inner.after_write()?;
+        }
+        Ok(restored)
+    }
+}
@@ -0,0 +1,381 @@
+//! `microkv`: inspect and edit stores from the command line. Values are shown (and taken)
+//! as JSON, converted from (and to) the msgpack the store seals.
+
+use std::collections::BTreeMap;
+use std::io::{self, Read};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::Duration;
+
+use clap::{Args, Parser, Subcommand};
+use serde_json::Value;
+use zeroize::{Zeroize, Zeroizing};
+
+use microkv::{Config, Credential, LockMode, MicroKV, PlaintextAck, SlotKind, Tree};
+
+type CliResult<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+#[derive(Parser)]
+#[command(name = "microkv", version, about = "Inspect and edit microkv stores")]
+struct Cli {
+    /// Path to the store.
+    #[arg(short, long, env = "MICROKV_STORE")]
+    store: PathBuf,
+
+    /// Namespace to work in; the default namespace if omitted.
+    #[arg(short, long, default_value = "", global = true)]
+    namespace: String,
+
+    #[command(flatten)]
+    password: PasswordArgs,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+/// Where to read the store's password from; prompts on the TTY by default.
+#[derive(Args)]
+struct PasswordArgs {
+    /// Take the password from this environment variable.
+    #[arg(long, value_name = "VAR", global = true)]
+    password_env: Option<String>,
+
+    /// Read the password from this open file descriptor (first line).
+    #[arg(
+        long,
+        value_name = "FD",
+        global = true,
+        conflicts_with = "password_env"
+    )]
+    password_fd: Option<i32>,
+}
+
+/// Where to read a new password from, for `passwd`; prompts twice by default.
+#[derive(Args)]
+struct NewPasswordArgs {
+    /// Take the new password from this environment variable.
+    #[arg(long, value_name = "VAR")]
+    new_password_env: Option<String>,
+
+    /// Read the new password from this open file descriptor (first line).
+    #[arg(long, value_name = "FD", conflicts_with = "new_password_env")]
+    new_password_fd: Option<i32>,
+}
+
+/// The flag `export` and `import` refuse to run without.
+#[derive(Args)]
+struct Unencrypted {
+    /// Confirm that the dump holds every value in plaintext.
+    #[arg(long)]
+    i_understand_this_is_unencrypted: bool,
+}
+
+impl Unencrypted {
+    fn ack(&self) -> CliResult<PlaintextAck> {
+        match self.i_understand_this_is_unencrypted {
+            true => Ok(PlaintextAck::I_UNDERSTAND_THIS_IS_UNENCRYPTED),
+            false => Err("dumps are unencrypted; pass --i-understand-this-is-unencrypted".into()),
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print a value as JSON.
+    Get { key: String },
+    /// Set a key to a string (or, with `--json`, any JSON value), creating the store if
+    /// needed.
+    Put {
+        key: String,
+        value: String,
+        /// Parse the value as JSON, e.g. to store a number or an object.
+        #[arg(long)]
+        json: bool,
+        /// Expire the entry after this many seconds.
+        #[arg(long, value_name = "SECS")]
+        ttl: Option<u64>,
+    },
+    /// Remove a key.
+    Rm { key: String },
+    /// List the namespace's keys.
+    Ls,
+    /// List the namespaces holding data.
+    Namespaces,
+    /// Print the namespace's pairs as a JSON object. Unencrypted!
+    Export {
+        /// Dump every namespace, with expiries, in the `export_plaintext` format.
+        #[arg(long)]
+        all: bool,
+        #[command(flatten)]
+        unencrypted: Unencrypted,
+    },
+    /// Put every pair of a JSON object, as printed by `export`, from a file or stdin.
+    Import {
+        file: Option<PathBuf>,
+        /// Load a full dump, as printed by `export --all`.
+        #[arg(long)]
+        all: bool,
+        #[command(flatten)]
+        unencrypted: Unencrypted,
+    },
+    /// Change the password.
+    Passwd {
+        #[command(flatten)]
+        new: NewPasswordArgs,
+    },
+    /// Re-encrypt every value under a fresh data key.
+    Rekey,
+    /// Drop expired entries.
+    Sweep,
+    /// Summarize the store: generation, namespaces, key slots.
+    Info,
+}
+
+impl Command {
+    fn writes(&self) -> bool {
+        !matches!(
+            self,
+            Command::Get { .. }
+                | Command::Ls
+                | Command::Namespaces
+                | Command::Export { .. }
+                | Command::Info
+        )
+    }
+}
+
+fn main() -> ExitCode {
+    match run(Cli::parse()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("microkv: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(cli: Cli) -> CliResult<()> {
+    // refuse before asking for the password
+    if let Command::Export { unencrypted, .. } | Command::Import { unencrypted, .. } = &cli.command
+    {
+        unencrypted.ack()?;
+    }
+    let password = read_password(
+        cli.password.password_env.as_deref(),
+        cli.password.password_fd,
+        "Password: ",
+    )?;
+    let writes = cli.command.writes();
+    let config = Config {
+        read_only: !writes,
+        lock_mode: if writes {
+            LockMode::Exclusive
+        } else {
+            LockMode::Shared
+        },
+        ..Default::default()
+    };
+    let cred = Credential::password(password.as_str());
+    let db = match cli.command {
+        Command::Put { .. } | Command::Import { .. } => {
+            MicroKV::open_with(&cli.store, cred, config)?
+        }
+        _ => MicroKV::open_existing_with(&cli.store, cred, config)?,
+    };
+    let tree = db.namespace(&cli.namespace);
+
+    match cli.command {
+        Command::Get { key } => {
+            let value: Value = tree.require(&key)?;
+            println!("{value}");
+        }
+        Command::Put {
+            key,
+            value,
+            json,
+            ttl,
+        } => {
+            let value = match json {
+                true => serde_json::from_str(&value)?,
+                false => Value::String(value),
+            };
+            match ttl {
+                Some(secs) => tree.put_with_ttl(&key, &value, Duration::from_secs(secs))?,
+                None => tree.put(&key, &value)?,
+            }
+        }
+        Command::Rm { key } => {
+            if !tree.remove(&key)? {
+                return Err(microkv::Error::KeyNotFound.into());
+            }
+        }
+        Command::Ls => {
+            for key in tree.keys_sorted()? {
+                println!("{key}");
+            }
+        }
+        Command::Namespaces => {
+            let mut names = db.tree_names()?;
+            names.sort();
+            for name in names {
+                println!("{name}");
+            }
+        }
+        Command::Export {
+            all: true,
+            unencrypted,
+        } => {
+            db.export_plaintext(io::stdout().lock(), unencrypted.ack()?)?;
+            println!();
+        }
+        Command::Export { all: false, .. } => {
+            println!("{}", serde_json::to_string_pretty(&dump(&tree)?)?);
+        }
+        Command::Import {
+            file,
+            all,
+            unencrypted,
+        } => {
+            let mut raw = Vec::new();
+            match file {
+                Some(path) => raw = std::fs::read(path)?,
+                None => {
+                    io::stdin().read_to_end(&mut raw)?;
+                }
+            }
+            if all {
+                db.import_plaintext(&raw[..], unencrypted.ack()?)?;
+            } else {
+                load(&tree, serde_json::from_slice(&raw)?)?;
+            }
+        }
+        Command::Passwd { new } => {
+            let new = read_new_password(&new)?;
+            db.change_password(password.as_str(), new.as_str())?;
+        }
+        Command::Rekey => db.rotate_data_key()?,
+        Command::Sweep => println!("{}", db.sweep_expired()?),
+        Command::Info => info(&cli.store, &db)?,
+    }
+
+    if writes {
+        db.save()?;
+    }
+    Ok(())
+}
+
+/// A namespace's live pairs, with values as JSON.
+fn dump(tree: &Tree) -> CliResult<BTreeMap<String, Value>> {
+    let mut out = BTreeMap::new();
+    for key in tree.keys()? {
+        if let Some(value) = tree.get::<Value>(&key)? {
+            out.insert(key, value);
+        }
+    }
+    Ok(out)
+}
+
+fn load(tree: &Tree, pairs: Value) -> CliResult<()> {
+    for (key, value) in as_object(pairs)? {
+        tree.put(&key, &value)?;
+    }
+    Ok(())
+}
+
+fn as_object(value: Value) -> CliResult<serde_json::Map<String, Value>> {
+    match value {
+        Value::Object(map) => Ok(map),
+        _ => Err("expected a JSON object".into()),
+    }
+}
+
+fn info(path: &std::path::Path, db: &MicroKV) -> CliResult<()> {
+    println!("store:      {}", path.display());
+    println!("generation: {}", db.generation());
+    let mut names = db.tree_names()?;
+    names.sort();
+    println!("namespaces: {}", names.len());
+    for name in names {
+        let label = if name.is_empty() { "(default)" } else { &name };
+        println!("  {label}: {} keys", db.namespace(&name).len()?);
+    }
+    let slots = db.list_slots()?;
+    println!("key slots:  {}", slots.len());
+    for slot in slots {
+        let kind = match slot.kind {
+            SlotKind::Password => "password",
+            SlotKind::Key => "key",
+        };
+        let active = if slot.active { " (this password)" } else { "" };
+        println!("  {}: {kind}{active}", slot.id);
+    }
+    Ok(())
+}
+
+fn read_password(env: Option<&str>, fd: Option<i32>, prompt: &str) -> CliResult<Zeroizing<String>> {
+    if let Some(var) = env {
+        return std::env::var(var)
+            .map(Zeroizing::new)
+            .map_err(|_| format!("environment variable {var} is not set").into());
+    }
+    if let Some(fd) = fd {
+        return read_fd(fd);
+    }
+    Ok(Zeroizing::new(rpassword::prompt_password(prompt)?))
+}
+
+fn read_new_password(args: &NewPasswordArgs) -> CliResult<Zeroizing<String>> {
+    if args.new_password_env.is_some() || args.new_password_fd.is_some() {
+        return read_password(
+            args.new_password_env.as_deref(),
+            args.new_password_fd,
+            "New password: ",
+        );
+    }
+    let new = read_password(None, None, "New password: ")?;
+    let again = read_password(None, None, "Repeat new password: ")?;
+    if new != again {
+        return Err("passwords do not match".into());
+    }
+    Ok(new)
+}
+
+#[cfg(unix)]
+fn read_fd(fd: i32) -> CliResult<Zeroizing<String>> {
+    use std::mem::ManuallyDrop;
+    use std::os::fd::FromRawFd;
+
+    if fd < 0 || fd == 1 || fd == 2 {
+        return Err(format!("cannot read a password from file descriptor {fd}").into());
+    }
+    // SAFETY: the descriptor was handed to us to read the password from. It's borrowed,
+    // not owned: left open, so stdin (fd 0) still works afterwards.
+    let file = ManuallyDrop::new(unsafe { std::fs::File::from_raw_fd(fd) });
+    // a byte at a time, so nothing past the newline is consumed (stdin may carry more)
+    let mut line = Zeroizing::new(Vec::with_capacity(128));
+    let mut byte = [0u8; 1];
+    loop {
+        match (&*file).read(&mut byte) {
+            Ok(0) => break,
+            Ok(_) if byte[0] == b'\n' => break,
+            Ok(_) => line.push(byte[0]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    match String::from_utf8(std::mem::take(&mut *line)) {
+        Ok(password) => Ok(Zeroizing::new(password)),
+        Err(e) => {
+            e.into_bytes().zeroize();
+            Err("the password isn't valid UTF-8".into())
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn read_fd(_fd: i32) -> CliResult<Zeroizing<String>> {
+    Err("--password-fd is only supported on Unix".into())
+}
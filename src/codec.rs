@@ -1,18 +1,132 @@
-//! Value <-> msgpack conversion.
+//! Value <-> bytes conversion, in the [`Codec`] a namespace's values are stored with.
 
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 use zeroize::Zeroize;
 
+use crate::config::Codec;
 use crate::error::{Error, Result};
 
-pub(crate) fn encode<V: Serialize>(value: &V) -> Result<Vec<u8>> {
-    rmp_serde::to_vec(value).map_err(|e| Error::Serialization(e.to_string()))
+fn ser_err(e: impl std::fmt::Display) -> Error {
+    Error::Serialization(e.to_string())
 }
 
-/// Decode, then wipe the plaintext buffer so decrypted bytes don't linger.
-pub(crate) fn decode<V: DeserializeOwned>(mut bytes: Vec<u8>) -> Result<V> {
-    let out = rmp_serde::from_slice(&bytes).map_err(|e| Error::Serialization(e.to_string()));
+impl Codec {
+    pub(crate) fn encode<V: Serialize>(self, value: &V) -> Result<Vec<u8>> {
+        match self {
+            Codec::MessagePack => rmp_serde::to_vec(value).map_err(ser_err),
+            #[cfg(feature = "json")]
+            Codec::Json => serde_json::to_vec(value).map_err(ser_err),
+            #[cfg(feature = "cbor")]
+            Codec::Cbor => {
+                let mut out = Vec::new();
+                ciborium::into_writer(value, &mut out).map_err(ser_err)?;
+                Ok(out)
+            }
+            #[cfg(feature = "postcard")]
+            Codec::Postcard => postcard::to_allocvec(value).map_err(ser_err),
+            #[cfg(feature = "bincode")]
+            Codec::Bincode => bincode::serialize(value).map_err(ser_err),
+            #[allow(unreachable_patterns)]
+            _ => Err(self.disabled()),
+        }
+    }
+
+    /// Decode, then wipe the plaintext buffer so decrypted bytes don't linger.
+    pub(crate) fn decode<V: DeserializeOwned>(self, mut bytes: Vec<u8>) -> Result<V> {
+        let out = match self {
+            Codec::MessagePack => rmp_serde::from_slice(&bytes).map_err(ser_err),
+            #[cfg(feature = "json")]
+            Codec::Json => serde_json::from_slice(&bytes).map_err(ser_err),
+            #[cfg(feature = "cbor")]
+            Codec::Cbor => ciborium::from_reader(bytes.as_slice()).map_err(ser_err),
+            #[cfg(feature = "postcard")]
+            Codec::Postcard => postcard::from_bytes(&bytes).map_err(ser_err),
+            #[cfg(feature = "bincode")]
+            Codec::Bincode => bincode::deserialize(&bytes).map_err(ser_err),
+            #[allow(unreachable_patterns)]
+            _ => Err(self.disabled()),
+        };
+        bytes.zeroize();
+        out
+    }
+
+    /// Fails if this codec isn't compiled in.
+    pub(crate) fn check(self) -> Result<()> {
+        match self {
+            Codec::MessagePack => Ok(()),
+            #[cfg(feature = "json")]
+            Codec::Json => Ok(()),
+            #[cfg(feature = "cbor")]
+            Codec::Cbor => Ok(()),
+            #[cfg(feature = "postcard")]
+            Codec::Postcard => Ok(()),
+            #[cfg(feature = "bincode")]
+            Codec::Bincode => Ok(()),
+            #[allow(unreachable_patterns)]
+            _ => Err(self.disabled()),
+        }
+    }
+
+    /// Values can be decoded without knowing their type.
+    #[cfg(feature = "json")]
+    pub(crate) fn self_describing(self) -> bool {
+        !matches!(self, Codec::Postcard | Codec::Bincode)
+    }
+
+    fn disabled(self) -> Error {
+        let feature = match self {
+            Codec::MessagePack => "",
+            Codec::Json => "json",
+            Codec::Cbor => "cbor",
+            Codec::Postcard => "postcard",
+            Codec::Bincode => "bincode",
+        };
+        Error::CorruptStore(format!(
+            "store uses the {self:?} codec but the '{feature}' feature is disabled"
+        ))
+    }
+}
+
+/// A raw value, framed as a msgpack bin without going through serde (whatever the
+/// namespace's codec). In msgpack namespaces, values stored this way still decode as bytes
+/// (e.g. `serde_bytes::ByteBuf`).
+pub(crate) fn encode_raw(value: &[u8]) -> Result<Vec<u8>> {
+    let len = value.len();
+    let mut out = Vec::with_capacity(len + 5);
+    if let Ok(n) = u8::try_from(len) {
+        out.extend_from_slice(&[0xc4, n]);
+    } else if let Ok(n) = u16::try_from(len) {
+        out.push(0xc5);
+        out.extend_from_slice(&n.to_be_bytes());
+    } else {
+        let n = u32::try_from(len)
+            .map_err(|_| Error::Serialization("raw value too large".to_string()))?;
+        out.push(0xc6);
+        out.extend_from_slice(&n.to_be_bytes());
+    }
+    out.extend_from_slice(value);
+    Ok(out)
+}
+
+/// Inverse of [`encode_raw`]. The payload is copied out and `bytes` wiped, so no
+/// plaintext is left behind in its spare capacity.
+pub(crate) fn decode_raw(mut bytes: Vec<u8>) -> Result<Vec<u8>> {
+    let header = match bytes.first() {
+        Some(0xc4) => 2,
+        Some(0xc5) => 3,
+        Some(0xc6) => 5,
+        _ => 0,
+    };
+    let len = bytes
+        .get(1..header)
+        .filter(|_| header > 0)
+        .map(|n| n.iter().fold(0usize, |len, &b| len << 8 | b as usize));
+    if len.and_then(|len| len.checked_add(header)) != Some(bytes.len()) {
+        bytes.zeroize();
+        return Err(Error::Serialization("not a raw value".to_string()));
+    }
+    let payload = bytes[header..].to_vec();
     bytes.zeroize();
-    out
+    Ok(payload)
 }
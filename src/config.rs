@@ -1,5 +1,7 @@
 //! Public config types and key derivation.
 
+use std::collections::BTreeMap;
+use std::sync::Arc;
 use std::time::Duration;
 
 use serde::{Deserialize, Serialize};
@@ -26,6 +28,13 @@ impl Credential {
     pub fn key(key: [u8; KEY_LEN]) -> Self {
         Credential::Key(key)
     }
+
+    pub(crate) fn kind(&self) -> SlotKind {
+        match self {
+            Credential::Password(_) => SlotKind::Password,
+            Credential::Key(_) => SlotKind::Key,
+        }
+    }
 }
 
 impl Drop for Credential {
@@ -37,6 +46,24 @@ impl Drop for Credential {
     }
 }
 
+/// Which kind of [`Credential`] a key slot takes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SlotKind {
+    Password,
+    Key,
+}
+
+/// A key slot, as listed by [`MicroKV::list_slots`](crate::MicroKV::list_slots).
+#[derive(Debug, Clone)]
+pub struct SlotInfo {
+    /// Pass to [`MicroKV::remove_credential`](crate::MicroKV::remove_credential).
+    pub id: u32,
+    pub kind: SlotKind,
+    /// This handle was opened through this slot (and [`MicroKV::rekey`](crate::MicroKV::rekey)
+    /// replaces it).
+    pub active: bool,
+}
+
 /// KDF algorithm + cost, persisted in the header so the work factor can change without
 /// breaking existing stores.
 #[derive(Clone, Serialize, Deserialize)]
@@ -89,6 +116,76 @@ impl Default for KdfParams {
     }
 }
 
+/// The AEAD values are sealed with, stamped into the header of new stores.
+///
+/// Every cipher takes a fresh random nonce per seal. ChaCha20-Poly1305's 96-bit nonces
+/// limit how many values can safely be sealed under one data key (around 2^32); the
+/// alternatives lift that for write-heavy stores.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Cipher {
+    /// The only cipher of stores before v4.
+    #[default]
+    ChaCha20Poly1305,
+    /// 192-bit nonces: random nonces never realistically collide.
+    XChaCha20Poly1305,
+    /// Misuse-resistant: a repeated nonce leaks only whether two values were equal.
+    /// Requires the `aes-gcm-siv` feature.
+    Aes256GcmSiv,
+}
+
+/// How values are serialized before sealing, stamped into the header of new stores (for
+/// the whole store, and optionally per namespace) so readers decode them the same way.
+///
+/// Postcard and bincode aren't self-describing: their values can't be decoded without
+/// knowing the type, so namespaces using them can't be dumped as JSON.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Codec {
+    /// The only codec of stores before v6.
+    #[default]
+    MessagePack,
+    /// Requires the `json` feature.
+    Json,
+    /// Requires the `cbor` feature.
+    Cbor,
+    /// Requires the `postcard` feature.
+    Postcard,
+    /// bincode 1.x. Requires the `bincode` feature.
+    Bincode,
+}
+
+/// How sealed values are padded to hide their length, stamped into the header of new
+/// stores. The padding covers the whole sealed frame: value, expiry, and any names.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Padding {
+    #[default]
+    None,
+    /// Up to the next power of two, so lengths leak only their magnitude.
+    PowerOfTwo,
+    /// Up to a multiple of this many bytes.
+    Block(u32),
+    /// Values in each listed namespace are padded up to its size (to a multiple of it when
+    /// larger), so they all look alike; other namespaces aren't padded. With
+    /// [`Config::encrypt_names`] the header records blind indexes, not the names.
+    Namespaces(BTreeMap<String, u32>),
+}
+
+impl Padding {
+    /// The padded length of a `len`-byte frame stored under namespace map key `ns_id`, or
+    /// `None` if it isn't padded.
+    pub(crate) fn padded_len(&self, ns_id: &str, len: usize) -> Option<usize> {
+        let block = |n: u32| match n as usize {
+            0 => len,
+            n => len.div_ceil(n) * n,
+        };
+        match self {
+            Padding::None => None,
+            Padding::PowerOfTwo => Some(len.next_power_of_two()),
+            Padding::Block(n) => Some(block(*n)),
+            Padding::Namespaces(sizes) => sizes.get(ns_id).map(|&n| block(n)),
+        }
+    }
+}
+
 /// When to flush to disk.
 #[derive(Clone, Copy, Default)]
 pub enum AutoSave {
@@ -96,8 +193,12 @@ pub enum AutoSave {
     #[default]
     Manual,
     OnEveryWrite,
-    /// Save on a write if this much time has passed since the last save.
+    /// Save unsaved changes this often, from a background thread, and when the last handle
+    /// drops.
     Periodic(Duration),
+    /// Save once writes have stopped for this long, from a background thread: a burst of
+    /// writes is saved once, after the last of them. Also saves when the last handle drops.
+    Debounced(Duration),
     /// Save when the last handle drops.
     OnDrop,
 }
@@ -111,16 +212,96 @@ pub enum LockMode {
     Exclusive,
 }
 
+/// What [`MicroKV::restore_from`](crate::MicroKV::restore_from) does with a key that
+/// already holds a live value.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OnConflict {
+    /// Restore nothing, failing with [`Error::Conflict`](crate::Error::Conflict).
+    #[default]
+    Fail,
+    /// Keep the existing value.
+    Skip,
+    /// Replace it with the backup's.
+    Overwrite,
+}
+
 /// Open-time knobs. Everything defaults: `Config { read_only: true, ..Default::default() }`.
-#[derive(Clone, Default)]
+#[derive(Clone)]
 pub struct Config {
-    /// Stamped into *new* stores only; ignored when opening an existing one.
+    /// Stamped into *new* stores only; ignored when opening an existing one (see
+    /// [`MicroKV::upgrade_kdf`](crate::MicroKV::upgrade_kdf)).
     pub kdf: KdfParams,
     pub autosave: AutoSave,
     pub lock_mode: LockMode,
     pub read_only: bool,
+    /// Persist writes by appending them to a `<path>.wal` journal instead of rewriting the
+    /// whole file; the journal is folded back in once it outgrows the base file, or on
+    /// [`MicroKV::compact`](crate::MicroKV::compact). An existing journal is replayed on
+    /// open regardless of this flag. Ignored for backends without a journal (see
+    /// [`Backend::supports_journal`](crate::Backend::supports_journal)).
+    pub wal: bool,
+    /// Stamped into *new* stores only.
+    pub cipher: Cipher,
+    /// Stamped into *new* stores only.
+    pub padding: Padding,
+    /// How values are serialized. Stamped into *new* stores only.
+    pub codec: Codec,
+    /// Per-namespace overrides of [`Config::codec`]. Stamped into *new* stores only; with
+    /// [`Config::encrypt_names`] the header records blind indexes, not the names.
+    pub namespace_codecs: BTreeMap<String, Codec>,
+    /// Store namespace and key names as keyed blind indexes, sealing the names themselves
+    /// inside each entry. Stamped into *new* stores only.
+    pub encrypt_names: bool,
+    /// Let a writable open upgrade a store written by an older format version, rewriting it
+    /// in the current one (which older releases can't read). On by default, so old stores
+    /// just open. Turn it off to keep such stores readable by the release that wrote them:
+    /// they then only open read-only, and writable opens fail with
+    /// [`Error::UnsupportedStoreVersion`](crate::Error::UnsupportedStoreVersion).
+    pub allow_upgrade: bool,
+    /// Open a damaged store instead of refusing it: entries that fail authentication are
+    /// dropped, a mismatched integrity tag is tolerated, journal replay stops at the first
+    /// bad record, and a file that doesn't decode keeps the entries before the damage. What
+    /// was set aside is in
+    /// [`MicroKV::salvage_report`](crate::MicroKV::salvage_report).
+    pub salvage: bool,
+    /// Reject a store whose generation ([`MicroKV::generation`](crate::MicroKV::generation))
+    /// is lower than this with [`Error::Rollback`]. Persist the last generation you saw
+    /// somewhere the attacker can't reach to catch whole-file rollbacks; `0` accepts any.
+    pub min_generation: u64,
+    /// Drop expired entries from a background thread this often, as
+    /// [`MicroKV::sweep_expired`](crate::MicroKV::sweep_expired) does. The thread stops
+    /// when the last handle drops. Ignored for read-only opens.
+    pub sweep_interval: Option<Duration>,
+    /// Called from the sweeper thread after each sweep that removed entries (with how
+    /// many) or failed.
+    pub on_sweep: Option<SweepHook>,
 }
 
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            kdf: KdfParams::default(),
+            autosave: AutoSave::default(),
+            lock_mode: LockMode::default(),
+            read_only: false,
+            wal: false,
+            cipher: Cipher::default(),
+            padding: Padding::default(),
+            codec: Codec::default(),
+            namespace_codecs: BTreeMap::new(),
+            encrypt_names: false,
+            allow_upgrade: true,
+            salvage: false,
+            min_generation: 0,
+            sweep_interval: None,
+            on_sweep: None,
+        }
+    }
+}
+
+/// The callback for [`Config::on_sweep`].
+pub type SweepHook = Arc<dyn Fn(Result<usize>) + Send + Sync>;
+
 /// The 32-byte key for a credential: raw keys as-is, passwords run through the KDF.
 pub(crate) fn credential_key(
     cred: &Credential,
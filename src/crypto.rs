@@ -2,12 +2,20 @@
 
 use std::ptr::NonNull;
 
+#[cfg(feature = "aes-gcm-siv")]
+use aes_gcm_siv::Aes256GcmSiv;
 use chacha20poly1305::aead::{Aead, KeyInit, Payload};
-use chacha20poly1305::{ChaCha20Poly1305, Nonce};
+use chacha20poly1305::{ChaCha20Poly1305, XChaCha20Poly1305};
+use hmac::{Hmac, Mac};
+use poly1305::Poly1305;
+use salsa20::cipher::{KeyIvInit, StreamCipher};
+use salsa20::XSalsa20;
+use sha2::Sha256;
 use zeroize::{Zeroize, Zeroizing};
 
-use crate::config::KdfRepr;
+use crate::config::{Cipher, KdfRepr};
 use crate::error::{Error, Result};
+use crate::format::KeySlot;
 
 pub(crate) const KEY_LEN: usize = 32;
 pub(crate) const SALT_LEN: usize = 16;
@@ -61,13 +69,95 @@ impl SecretKey {
         }
     }
 
-    pub(crate) fn cipher(&self) -> ChaCha20Poly1305 {
-        let key: &[u8] = match &self.store {
+    /// A fresh random key, e.g. for sealing names.
+    pub(crate) fn random() -> Result<Self> {
+        let mut key = [0u8; KEY_LEN];
+        getrandom::getrandom(&mut key).map_err(|_| Error::Random)?;
+        Self::new(key)
+    }
+
+    /// A second handle on the same key, in its own allocation.
+    pub(crate) fn try_clone(&self) -> Result<Self> {
+        let mut key = [0u8; KEY_LEN];
+        key.copy_from_slice(self.bytes());
+        let secret = Self::new(key);
+        key.zeroize();
+        secret
+    }
+
+    fn bytes(&self) -> &[u8] {
+        match &self.store {
             // SAFETY: `ptr` is valid for `self`'s lifetime and never aliased mutably.
             KeyStore::Locked(ptr) => unsafe { ptr.as_ref() },
             KeyStore::Heap(z) => &z[..],
-        };
-        ChaCha20Poly1305::new_from_slice(key).expect("key length is KEY_LEN")
+        }
+    }
+
+    /// ChaCha20-Poly1305, which all key material (wrapped keys, the verifier) is sealed
+    /// with whatever the store's [`Cipher`].
+    pub(crate) fn cipher(&self) -> AeadCipher {
+        AeadCipher::ChaCha20Poly1305(
+            ChaCha20Poly1305::new_from_slice(self.bytes()).expect("key length is KEY_LEN"),
+        )
+    }
+
+    /// The store's value cipher, for entries and journal records.
+    pub(crate) fn cipher_as(&self, kind: Cipher) -> Result<AeadCipher> {
+        Ok(match kind {
+            Cipher::ChaCha20Poly1305 => self.cipher(),
+            Cipher::XChaCha20Poly1305 => AeadCipher::XChaCha20Poly1305(
+                XChaCha20Poly1305::new_from_slice(self.bytes()).expect("key length is KEY_LEN"),
+            ),
+            Cipher::Aes256GcmSiv => {
+                #[cfg(feature = "aes-gcm-siv")]
+                {
+                    AeadCipher::Aes256GcmSiv(Box::new(
+                        Aes256GcmSiv::new_from_slice(self.bytes()).expect("key length is KEY_LEN"),
+                    ))
+                }
+                #[cfg(not(feature = "aes-gcm-siv"))]
+                {
+                    return Err(Error::CorruptStore(
+                        "store uses AES-256-GCM-SIV but the 'aes-gcm-siv' feature is disabled"
+                            .to_string(),
+                    ));
+                }
+            }
+        })
+    }
+
+    /// HMAC-SHA256 over `parts`, concatenated.
+    pub(crate) fn mac(&self, parts: &[&[u8]]) -> [u8; 32] {
+        let mut mac =
+            <Hmac<Sha256> as Mac>::new_from_slice(self.bytes()).expect("HMAC takes any key size");
+        for part in parts {
+            mac.update(part);
+        }
+        mac.finalize().into_bytes().into()
+    }
+
+    /// An independent subkey for `label`, so one key never serves two purposes.
+    pub(crate) fn derive(&self, label: &[u8]) -> Result<Self> {
+        Self::new(self.mac(&[b"microkv/derive/", label]))
+    }
+
+    /// Seal this key under `cipher`; returns `(nonce, ciphertext+tag)`.
+    pub(crate) fn wrap(&self, cipher: &AeadCipher, aad: &[u8]) -> Result<(Vec<u8>, Vec<u8>)> {
+        aead_encrypt(cipher, aad, self.bytes())
+    }
+
+    /// Inverse of [`SecretKey::wrap`].
+    pub(crate) fn unwrap(
+        cipher: &AeadCipher,
+        aad: &[u8],
+        nonce: &[u8],
+        ciphertext: &[u8],
+    ) -> Result<Self> {
+        let plaintext = Zeroizing::new(aead_decrypt(cipher, aad, nonce, ciphertext)?);
+        let mut key: [u8; KEY_LEN] = plaintext[..].try_into().map_err(|_| Error::Crypto)?;
+        let secret = Self::new(key);
+        key.zeroize();
+        secret
     }
 }
 
@@ -81,41 +171,97 @@ impl Drop for SecretKey {
     }
 }
 
+/// A keyed AEAD, one of the [`Cipher`]s.
+pub(crate) enum AeadCipher {
+    ChaCha20Poly1305(ChaCha20Poly1305),
+    XChaCha20Poly1305(XChaCha20Poly1305),
+    /// Boxed: its expanded key schedule dwarfs the other variants.
+    #[cfg(feature = "aes-gcm-siv")]
+    Aes256GcmSiv(Box<Aes256GcmSiv>),
+}
+
+impl AeadCipher {
+    fn nonce_len(&self) -> usize {
+        match self {
+            AeadCipher::ChaCha20Poly1305(_) => 12,
+            AeadCipher::XChaCha20Poly1305(_) => 24,
+            #[cfg(feature = "aes-gcm-siv")]
+            AeadCipher::Aes256GcmSiv(_) => 12,
+        }
+    }
+}
+
 /// Seal under a fresh random nonce; returns `(nonce, ciphertext+tag)`.
 pub(crate) fn aead_encrypt(
-    cipher: &ChaCha20Poly1305,
+    cipher: &AeadCipher,
     aad: &[u8],
     plaintext: &[u8],
-) -> Result<([u8; 12], Vec<u8>)> {
-    let mut nonce_bytes = [0u8; 12];
-    getrandom::getrandom(&mut nonce_bytes).map_err(|_| Error::Random)?;
-    let ciphertext = cipher
-        .encrypt(
-            Nonce::from_slice(&nonce_bytes),
-            Payload {
-                msg: plaintext,
-                aad,
-            },
-        )
-        .map_err(|_| Error::Crypto)?;
-    Ok((nonce_bytes, ciphertext))
+) -> Result<(Vec<u8>, Vec<u8>)> {
+    let mut nonce = vec![0u8; cipher.nonce_len()];
+    getrandom::getrandom(&mut nonce).map_err(|_| Error::Random)?;
+    let payload = Payload {
+        msg: plaintext,
+        aad,
+    };
+    let ciphertext = match cipher {
+        AeadCipher::ChaCha20Poly1305(c) => c.encrypt(nonce[..].into(), payload),
+        AeadCipher::XChaCha20Poly1305(c) => c.encrypt(nonce[..].into(), payload),
+        #[cfg(feature = "aes-gcm-siv")]
+        AeadCipher::Aes256GcmSiv(c) => c.encrypt(nonce[..].into(), payload),
+    }
+    .map_err(|_| Error::Crypto)?;
+    Ok((nonce, ciphertext))
 }
 
+/// A nonce of the wrong length (e.g. from another cipher) fails like a bad tag.
 pub(crate) fn aead_decrypt(
-    cipher: &ChaCha20Poly1305,
+    cipher: &AeadCipher,
     aad: &[u8],
-    nonce: &[u8; 12],
+    nonce: &[u8],
     ciphertext: &[u8],
 ) -> Result<Vec<u8>> {
-    cipher
-        .decrypt(
-            Nonce::from_slice(nonce),
-            Payload {
-                msg: ciphertext,
-                aad,
-            },
-        )
-        .map_err(|_| Error::Crypto)
+    if nonce.len() != cipher.nonce_len() {
+        return Err(Error::Crypto);
+    }
+    let payload = Payload {
+        msg: ciphertext,
+        aad,
+    };
+    match cipher {
+        AeadCipher::ChaCha20Poly1305(c) => c.decrypt(nonce.into(), payload),
+        AeadCipher::XChaCha20Poly1305(c) => c.decrypt(nonce.into(), payload),
+        #[cfg(feature = "aes-gcm-siv")]
+        AeadCipher::Aes256GcmSiv(c) => c.decrypt(nonce.into(), payload),
+    }
+    .map_err(|_| Error::Crypto)
+}
+
+/// Nonce length of [`secretbox_open`].
+pub(crate) const SECRETBOX_NONCE_LEN: usize = 24;
+
+/// Open a NaCl `crypto_secretbox` (XSalsa20-Poly1305, tag first), as releases before 0.3
+/// sealed values.
+pub(crate) fn secretbox_open(
+    key: &[u8; KEY_LEN],
+    nonce: &[u8; SECRETBOX_NONCE_LEN],
+    sealed: &[u8],
+) -> Result<Zeroizing<Vec<u8>>> {
+    if sealed.len() < 16 {
+        return Err(Error::Crypto);
+    }
+    let (tag, ciphertext) = sealed.split_at(16);
+    // the first 32 bytes of keystream key the MAC; the message is XORed with the rest
+    let mut cipher = XSalsa20::new(key.into(), nonce.into());
+    let mut mac_key = Zeroizing::new([0u8; KEY_LEN]);
+    cipher.apply_keystream(&mut mac_key[..]);
+    let expected = Poly1305::new((&*mac_key).into()).compute_unpadded(ciphertext);
+    let diff = expected.iter().zip(tag).fold(0u8, |d, (a, b)| d | (a ^ b));
+    if diff != 0 {
+        return Err(Error::Crypto);
+    }
+    let mut plaintext = Zeroizing::new(ciphertext.to_vec());
+    cipher.apply_keystream(&mut plaintext);
+    Ok(plaintext)
 }
 
 /// AAD binding a value to its `(namespace, key)`. Length-prefixed so `(a, bc)` and
@@ -129,9 +275,39 @@ pub(crate) fn value_aad(ns: &str, key: &str) -> Vec<u8> {
     aad
 }
 
-/// AAD binding the header (KDF params + salt) to the verifier, so tampering is caught.
-pub(crate) fn header_aad(kdf: &KdfRepr, salt: &[u8; SALT_LEN]) -> Result<Vec<u8>> {
-    rmp_serde::to_vec(&(kdf, salt)).map_err(|e| Error::Serialization(e.to_string()))
+/// AAD binding the header (KDF params + salt + encoded features + key slots) to the
+/// verifier, so tampering is caught. v3 stores have neither features nor slots, and
+/// authenticate as before.
+pub(crate) fn header_aad(
+    kdf: &KdfRepr,
+    salt: &[u8; SALT_LEN],
+    features: &[u8],
+    slots: &[KeySlot],
+) -> Result<Vec<u8>> {
+    let mut aad =
+        rmp_serde::to_vec(&(kdf, salt)).map_err(|e| Error::Serialization(e.to_string()))?;
+    aad.extend_from_slice(features);
+    if !slots.is_empty() {
+        let slots = rmp_serde::to_vec(slots).map_err(|e| Error::Serialization(e.to_string()))?;
+        aad.extend_from_slice(&slots);
+    }
+    Ok(aad)
+}
+
+/// Blind index for a name: a truncated keyed HMAC, hex-encoded so it can stand in for the
+/// name as a map key. Parts are length-prefixed, like [`value_aad`].
+pub(crate) fn blind_index(key: &SecretKey, label: &[u8], names: &[&str]) -> String {
+    let mut parts: Vec<&[u8]> = vec![label];
+    let lens: Vec<[u8; 4]> = names
+        .iter()
+        .map(|n| (n.len() as u32).to_le_bytes())
+        .collect();
+    for (name, len) in names.iter().zip(&lens) {
+        parts.push(len);
+        parts.push(name.as_bytes());
+    }
+    let tag = key.mac(&parts);
+    tag[..16].iter().map(|b| format!("{b:02x}")).collect()
 }
 
 pub(crate) fn gen_salt() -> Result<[u8; SALT_LEN]> {
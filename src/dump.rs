@@ -0,0 +1,179 @@
+//! Decrypted JSON dumps of a whole store, for migrations and audits.
+//!
+//! Layout: `{"format": "microkv-plaintext", "version": 1, "namespaces": {ns: {key:
+//! {"value": .., "expires_at": ..}}}}`, with values transcoded from the namespace's codec
+//! and `expires_at` (unix seconds) present only on entries with a TTL. Values JSON can't
+//! hold are base64 instead of `"value"`: `"bytes"` for raw values
+//! ([`Tree::put_raw`](crate::Tree::put_raw)), and `"encoded": {"codec": .., "data": ..}`
+//! for anything else, as the codec encoded it.
+
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+
+use base64ct::{Base64, Encoding};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use zeroize::{Zeroize, Zeroizing};
+
+use crate::codec::{decode_raw, encode_raw};
+use crate::config::Codec;
+use crate::error::{Error, Result};
+use crate::format::now_secs;
+use crate::store::{seal_encoded_into, MicroKV};
+
+const DUMP_FORMAT: &str = "microkv-plaintext";
+const DUMP_VERSION: u32 = 1;
+
+/// Acknowledges that a plaintext dump is *not* encrypted: anyone who can read it can read
+/// every value. Required by [`MicroKV::export_plaintext`] and
+/// [`MicroKV::import_plaintext`].
+#[derive(Debug, Clone, Copy)]
+pub struct PlaintextAck(());
+
+impl PlaintextAck {
+    pub const I_UNDERSTAND_THIS_IS_UNENCRYPTED: PlaintextAck = PlaintextAck(());
+}
+
+#[derive(Serialize, Deserialize)]
+struct Dump {
+    format: String,
+    version: u32,
+    namespaces: BTreeMap<String, BTreeMap<String, DumpEntry>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct DumpEntry {
+    #[serde(flatten)]
+    value: DumpValue,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expires_at: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum DumpValue {
+    Value(Value),
+    /// A raw value, base64.
+    Bytes(String),
+    /// A value as `codec` encoded it, base64.
+    Encoded {
+        codec: Codec,
+        data: String,
+    },
+}
+
+impl DumpValue {
+    /// JSON if the value decodes to it, else tagged base64. Values in namespaces whose
+    /// codec isn't self-describing are kept as encoded, raw ones included, so they load
+    /// back byte for byte.
+    fn from_encoded(codec: Codec, plaintext: &[u8]) -> DumpValue {
+        if codec.self_describing() {
+            if let Ok(value) = codec.decode(plaintext.to_vec()) {
+                return DumpValue::Value(value);
+            }
+            if let Ok(mut raw) = decode_raw(plaintext.to_vec()) {
+                let out = DumpValue::Bytes(Base64::encode_string(&raw));
+                raw.zeroize();
+                return out;
+            }
+        }
+        DumpValue::Encoded {
+            codec,
+            data: Base64::encode_string(plaintext),
+        }
+    }
+
+    /// The value as `codec` stores it.
+    fn to_encoded(&self, codec: Codec, (ns, key): (&str, &str)) -> Result<Zeroizing<Vec<u8>>> {
+        let base64 = |data: &str| {
+            Base64::decode_vec(data)
+                .map(Zeroizing::new)
+                .map_err(|e| Error::Serialization(format!("{ns}/{key}: bad base64: {e}")))
+        };
+        match self {
+            DumpValue::Value(_) if !codec.self_describing() => Err(Error::Serialization(format!(
+                "namespace {ns:?} is encoded as {codec:?}, which JSON can't be imported into"
+            ))),
+            DumpValue::Value(value) => codec.encode(value).map(Zeroizing::new),
+            DumpValue::Bytes(data) => encode_raw(&base64(data)?).map(Zeroizing::new),
+            DumpValue::Encoded { codec: from, .. } if *from != codec => Err(Error::Serialization(
+                format!("{ns}/{key} was encoded as {from:?}, but namespace {ns:?} uses {codec:?}"),
+            )),
+            DumpValue::Encoded { data, .. } => base64(data),
+        }
+    }
+}
+
+impl MicroKV {
+    /// Write every live entry, decrypted, to `writer` as JSON. Values that JSON can't
+    /// represent (raw values, maps with non-string keys), or that can't be decoded without
+    /// their type (postcard and bincode namespaces), are written as base64, tagged so that
+    /// [`MicroKV::import_plaintext`] loads them back unchanged.
+    pub fn export_plaintext(&self, writer: impl Write, _ack: PlaintextAck) -> Result<()> {
+        let entries = {
+            let store = self.inner.read_store()?;
+            self.inner.open_all(&store, |_| true)?
+        };
+        let mut namespaces: BTreeMap<String, BTreeMap<String, DumpEntry>> = BTreeMap::new();
+        for (ns, key, frame) in entries {
+            let codec = self.inner.codec_by_name(&ns)?;
+            let value = DumpValue::from_encoded(codec, &frame.value);
+            namespaces.entry(ns).or_default().insert(
+                key,
+                DumpEntry {
+                    value,
+                    expires_at: frame.expires_at,
+                },
+            );
+        }
+        let dump = Dump {
+            format: DUMP_FORMAT.to_string(),
+            version: DUMP_VERSION,
+            namespaces,
+        };
+        serde_json::to_writer_pretty(writer, &dump).map_err(|e| Error::Serialization(e.to_string()))
+    }
+
+    /// Load a dump written by [`MicroKV::export_plaintext`], overwriting existing keys and
+    /// returning how many entries were written. Entries that have since expired are
+    /// skipped. The dump is imported whole or not at all, and watchers hear of it only once
+    /// it has been. JSON values can't be imported into namespaces using a codec that isn't
+    /// self-describing (postcard, bincode), nor encoded values into a namespace using
+    /// another codec.
+    pub fn import_plaintext(&self, reader: impl Read, _ack: PlaintextAck) -> Result<usize> {
+        self.inner.ensure_writable()?;
+        let dump: Dump = serde_json::from_reader(reader)
+            .map_err(|e| Error::Serialization(format!("cannot parse plaintext dump: {e}")))?;
+        if dump.format != DUMP_FORMAT || dump.version != DUMP_VERSION {
+            return Err(Error::Serialization(format!(
+                "not a {DUMP_FORMAT} v{DUMP_VERSION} dump"
+            )));
+        }
+
+        let now = now_secs();
+        let mut encoded = Vec::new();
+        for (ns, entries) in &dump.namespaces {
+            let codec = self.inner.codec_by_name(ns)?;
+            for (key, entry) in entries {
+                if entry.expires_at.is_some_and(|at| at <= now) {
+                    continue;
+                }
+                let plaintext = entry.value.to_encoded(codec, (ns, key))?;
+                encoded.push((ns, key, plaintext, entry.expires_at));
+            }
+        }
+
+        // all or nothing: a failure part way leaves the store as it was
+        let written = encoded.len();
+        self.on_working_copy(|store| {
+            for (ns, key, plaintext, expires_at) in encoded {
+                seal_encoded_into(&self.inner, store, ns, key, &plaintext, expires_at)?;
+            }
+            Ok(())
+        })?;
+        if written > 0 {
+            self.inner.after_write()?;
+        }
+        Ok(written)
+    }
+}
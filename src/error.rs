@@ -48,6 +48,32 @@ pub enum Error {
     #[error("entry expired")]
     Expired,
 
+    /// The entries don't match the store's integrity tag: some were deleted, added, or
+    /// replaced with older sealed copies.
+    #[error("store integrity check failed")]
+    Integrity,
+
+    /// The store is older than the generation the caller last saw
+    /// ([`Config::min_generation`](crate::Config::min_generation)): rolled back or truncated.
+    #[error("store rolled back to generation {found} (expected at least {expected})")]
+    Rollback { found: u64, expected: u64 },
+
+    #[error("no key slot with id {0}")]
+    NoSuchSlot(u32),
+
+    /// [`MicroKV::rotate_master_key`](crate::MicroKV::rotate_master_key) was given no
+    /// credential for this slot.
+    #[error("no credential given for key slot {0}")]
+    MissingCredential(u32),
+
+    /// Removing the slot would leave no credential able to open the store.
+    #[error("cannot remove the last key slot")]
+    LastSlot,
+
+    /// A restored key already holds a value (under [`OnConflict::Fail`](crate::OnConflict)).
+    #[error("key {key:?} already exists in namespace {namespace:?}")]
+    Conflict { namespace: String, key: String },
+
     /// Persistence attempted on an in-memory store.
     #[error("no path associated with store")]
     NoPath,
@@ -1,39 +1,295 @@
-//! On-disk format and crash-safe persistence.
+//! On-disk format.
 
-use std::fs::{self, File, OpenOptions};
-use std::io::Write;
-use std::path::{Path, PathBuf};
+use std::collections::BTreeMap;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
+use zeroize::Zeroize;
 
-use crate::config::{KdfRepr, LockMode};
-use crate::crypto::{rand_u64, SALT_LEN};
+use crate::config::{credential_key, Cipher, Codec, Credential, KdfRepr, Padding, SlotKind};
+use crate::crypto::{gen_salt, value_aad, SecretKey, SALT_LEN};
 use crate::error::{Error, Result};
 
 /// File magic; rejects foreign files.
 pub(crate) const MAGIC: &str = "microkv";
 
-pub(crate) const FORMAT_VERSION: u8 = 3;
+pub(crate) const FORMAT_VERSION: u8 = 6;
+
+/// Stores are written as the oldest version that can read them, but never older than this:
+/// v5 when they keep the master key as their data key and use only msgpack, v6 once they
+/// have a data key of their own (with per-entry epochs and retired keys) or another codec.
+pub(crate) const MIN_WRITE_VERSION: u8 = 5;
+
+/// Oldest version still read; v3 stores are v4 stores without features, and v4 stores are
+/// v5 stores without key slots.
+pub(crate) const MIN_FORMAT_VERSION: u8 = 3;
 
 /// Sealed under the key with the header as AAD: doubles as the password verifier and
 /// authenticates the header.
 pub(crate) const VERIFIER_PLAINTEXT: &[u8] = b"microkv/verify/v3";
 
+/// AAD for the sealed blind-index key.
+pub(crate) const NAME_KEY_AAD: &[u8] = b"microkv/names-key";
+
+/// Subkey label for [`Integrity`] tags.
+pub(crate) const MANIFEST_KEY_LABEL: &[u8] = b"manifest";
+
+/// AAD prefix for a data key sealed under the master key; its epoch follows.
+pub(crate) const DATA_KEY_AAD: &[u8] = b"microkv/data-key";
+
+/// AAD prefix for a master key wrapped in a [`KeySlot`]; the slot id follows.
+pub(crate) const KEY_SLOT_AAD: &[u8] = b"microkv/key-slot";
+
 /// A stored entry: per-value nonce + AEAD ciphertext bound to its `(namespace, key)`. The
 /// plaintext holds the value *and* any expiry, so expiry is encrypted and authenticated.
 #[derive(Clone, Serialize, Deserialize)]
 pub(crate) struct Entry {
-    pub(crate) nonce: [u8; 12],
+    /// Length depends on the store's [`Cipher`](crate::Cipher).
+    pub(crate) nonce: Vec<u8>,
     pub(crate) data: Vec<u8>,
+    /// Epoch of the data key a value is sealed under; always `0` for sealed keys and
+    /// records, and omitted when `0`.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub(crate) epoch: u32,
 }
 
-pub(crate) type Bucket = IndexMap<String, Entry>;
+impl Entry {
+    /// An `(nonce, ciphertext)` pair from [`SecretKey::wrap`] or the AEAD helpers.
+    pub(crate) fn new((nonce, data): (Vec<u8>, Vec<u8>)) -> Self {
+        Entry {
+            nonce,
+            data,
+            epoch: 0,
+        }
+    }
+}
+
+fn is_zero(epoch: &u32) -> bool {
+    *epoch == 0
+}
+
+/// key -> entry, ordered by map key: by name in the clear, by blind index otherwise.
+pub(crate) type Bucket = BTreeMap<String, Entry>;
 
 /// namespace -> bucket; the empty string is the default namespace.
 pub(crate) type Store = IndexMap<String, Bucket>;
 
+/// Optional format features, fixed when a store is created. Encoded by field name (so
+/// fields can be added later) and kept as raw bytes in the file, which the verifier
+/// authenticates as-is.
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct Features {
+    /// Map keys are blind indexes; the real names are sealed inside each entry.
+    pub(crate) encrypted_names: bool,
+    /// Seals entries and journal records.
+    pub(crate) cipher: Cipher,
+    /// Applied to each sealed value; [`Padding::Namespaces`] is keyed by map key.
+    pub(crate) padding: Padding,
+    /// The file (and each journal record) carries an [`Integrity`] tag, checked at open.
+    pub(crate) manifest: bool,
+    /// The master key is random and wrapped once per credential, in [`KeySlot`]s; otherwise
+    /// it's derived directly from the one credential via the header's KDF and salt.
+    pub(crate) key_slots: bool,
+    /// Serializes values.
+    pub(crate) codec: Codec,
+    /// Per-namespace overrides of `codec`, keyed by map key.
+    pub(crate) namespace_codecs: BTreeMap<String, Codec>,
+}
+
+impl Features {
+    /// The version a store with these features needs. Releases before v6 ignore the codec
+    /// fields, so stores that use them are marked v6.
+    pub(crate) fn version(&self) -> u8 {
+        let msgpack = self.codec == Codec::MessagePack
+            && self
+                .namespace_codecs
+                .values()
+                .all(|&c| c == Codec::MessagePack);
+        if msgpack {
+            MIN_WRITE_VERSION
+        } else {
+            FORMAT_VERSION
+        }
+    }
+
+    pub(crate) fn encode(&self) -> Result<Vec<u8>> {
+        rmp_serde::to_vec_named(self).map_err(|e| Error::Serialization(e.to_string()))
+    }
+
+    /// v3 stores carry no features at all.
+    pub(crate) fn decode(raw: &[u8]) -> Result<Self> {
+        if raw.is_empty() {
+            return Ok(Features::default());
+        }
+        rmp_serde::from_slice(raw)
+            .map_err(|e| Error::CorruptStore(format!("cannot deserialize features: {e}")))
+    }
+}
+
+/// Whole-store authenticator: a MAC over every entry's `(namespace, key, nonce)` and a
+/// generation counter bumped on each save. Catches entries that were deleted, added, or
+/// swapped for older sealed copies, which per-entry AAD alone can't.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub(crate) struct Integrity {
+    pub(crate) generation: u64,
+    pub(crate) tag: [u8; 32],
+}
+
+impl Integrity {
+    /// Constant-time comparison.
+    pub(crate) fn matches(&self, other: &Integrity) -> bool {
+        let diff = self
+            .tag
+            .iter()
+            .zip(other.tag)
+            .fold(0u8, |d, (a, b)| d | (a ^ b));
+        diff == 0 && self.generation == other.generation
+    }
+}
+
+/// The entry-dependent half of an [`Integrity`] tag: per-entry MACs XOR-folded, so the
+/// tag doesn't depend on map order (which journal replay needn't reproduce), and so one
+/// entry can be folded in or back out without rescanning the rest.
+#[derive(Clone, Copy, Default)]
+pub(crate) struct Tally {
+    acc: [u8; 32],
+    count: u64,
+}
+
+impl Tally {
+    pub(crate) fn of(key: &SecretKey, store: &Store) -> Self {
+        let mut tally = Tally::default();
+        for (ns, bucket) in store {
+            for (k, entry) in bucket {
+                tally.add(key, (ns, k), &entry.nonce);
+            }
+        }
+        tally
+    }
+
+    pub(crate) fn add(&mut self, key: &SecretKey, ids: (&str, &str), nonce: &[u8]) {
+        self.fold(key, ids, nonce);
+        self.count = self.count.wrapping_add(1);
+    }
+
+    pub(crate) fn remove(&mut self, key: &SecretKey, ids: (&str, &str), nonce: &[u8]) {
+        self.fold(key, ids, nonce);
+        self.count = self.count.wrapping_sub(1);
+    }
+
+    fn fold(&mut self, key: &SecretKey, (ns, k): (&str, &str), nonce: &[u8]) {
+        let t = key.mac(&[b"entry", &value_aad(ns, k), nonce]);
+        self.acc.iter_mut().zip(t).for_each(|(a, b)| *a ^= b);
+    }
+
+    /// The tag at `generation`; `key` is the store key's manifest subkey.
+    pub(crate) fn seal(&self, key: &SecretKey, generation: u64) -> Integrity {
+        let tag = key.mac(&[
+            b"manifest",
+            &generation.to_le_bytes(),
+            &self.count.to_le_bytes(),
+            &self.acc,
+        ]);
+        Integrity { generation, tag }
+    }
+}
+
+/// The master key, wrapped under one credential. Any slot unlocks the store, so credentials
+/// can be added, removed or changed without touching the entries.
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct KeySlot {
+    /// Stable across removals of other slots.
+    pub(crate) id: u32,
+    pub(crate) kind: SlotKind,
+    /// Derives the wrapping key from a password; unused for raw keys.
+    pub(crate) kdf: KdfRepr,
+    pub(crate) salt: [u8; SALT_LEN],
+    pub(crate) wrapped: Entry,
+}
+
+impl KeySlot {
+    /// Wrap `key` under `cred`, with a fresh salt.
+    pub(crate) fn seal(id: u32, cred: &Credential, kdf: &KdfRepr, key: &SecretKey) -> Result<Self> {
+        let salt = gen_salt()?;
+        let mut wrapping = credential_key(cred, kdf, &salt)?;
+        let wrapping_key = SecretKey::new(wrapping)?;
+        wrapping.zeroize();
+        Self::seal_with(id, cred.kind(), kdf.clone(), salt, &wrapping_key, key)
+    }
+
+    /// Wrap `key` under an already derived `wrapping` key.
+    pub(crate) fn seal_with(
+        id: u32,
+        kind: SlotKind,
+        kdf: KdfRepr,
+        salt: [u8; SALT_LEN],
+        wrapping: &SecretKey,
+        key: &SecretKey,
+    ) -> Result<Self> {
+        Ok(KeySlot {
+            id,
+            kind,
+            kdf,
+            salt,
+            wrapped: Entry::new(key.wrap(&wrapping.cipher(), &Self::aad(id))?),
+        })
+    }
+
+    /// The master key, if `cred` is this slot's credential.
+    pub(crate) fn open(&self, cred: &Credential) -> Result<SecretKey> {
+        if cred.kind() != self.kind {
+            return Err(Error::WrongPassword);
+        }
+        let mut wrapping = credential_key(cred, &self.kdf, &self.salt)?;
+        let wrapping_key = SecretKey::new(wrapping)?;
+        wrapping.zeroize();
+        SecretKey::unwrap(
+            &wrapping_key.cipher(),
+            &Self::aad(self.id),
+            &self.wrapped.nonce,
+            &self.wrapped.data,
+        )
+        .map_err(|_| Error::WrongPassword)
+    }
+
+    fn aad(id: u32) -> Vec<u8> {
+        let mut aad = KEY_SLOT_AAD.to_vec();
+        aad.extend_from_slice(&id.to_le_bytes());
+        aad
+    }
+}
+
+/// A data key sealed under the master key, tagged with its epoch.
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct WrappedKey {
+    pub(crate) epoch: u32,
+    pub(crate) wrapped: Entry,
+}
+
+impl WrappedKey {
+    pub(crate) fn seal(epoch: u32, key: &SecretKey, master: &SecretKey) -> Result<Self> {
+        let wrapped = Entry::new(key.wrap(&master.cipher(), &Self::aad(epoch))?);
+        Ok(WrappedKey { epoch, wrapped })
+    }
+
+    pub(crate) fn open(&self, master: &SecretKey) -> Result<SecretKey> {
+        SecretKey::unwrap(
+            &master.cipher(),
+            &Self::aad(self.epoch),
+            &self.wrapped.nonce,
+            &self.wrapped.data,
+        )
+    }
+
+    fn aad(epoch: u32) -> Vec<u8> {
+        let mut aad = DATA_KEY_AAD.to_vec();
+        aad.extend_from_slice(&epoch.to_le_bytes());
+        aad
+    }
+}
+
 /// Borrowed for writing (avoids cloning); see [`StoreFile`] for the owned read side.
 #[derive(Serialize)]
 pub(crate) struct StoreFileRef<'a> {
@@ -43,6 +299,13 @@ pub(crate) struct StoreFileRef<'a> {
     pub(crate) salt: &'a [u8; SALT_LEN],
     pub(crate) verifier: &'a Entry,
     pub(crate) trees: &'a Store,
+    pub(crate) log_id: u64,
+    pub(crate) features: &'a [u8],
+    pub(crate) name_key: Option<&'a Entry>,
+    pub(crate) integrity: Option<Integrity>,
+    pub(crate) slots: &'a [KeySlot],
+    pub(crate) data_key: Option<&'a WrappedKey>,
+    pub(crate) retired_keys: Vec<&'a WrappedKey>,
 }
 
 /// Owned, read back from disk.
@@ -54,6 +317,30 @@ pub(crate) struct StoreFile {
     pub(crate) salt: [u8; SALT_LEN],
     pub(crate) verifier: Entry,
     pub(crate) trees: Store,
+    /// Id of the write-ahead log extending this file; `0` (and absent) when there is none.
+    #[serde(default)]
+    pub(crate) log_id: u64,
+    /// Encoded [`Features`]; empty for v3 stores.
+    #[serde(default)]
+    pub(crate) features: Vec<u8>,
+    /// Random key for blind indexes, sealed under the store key
+    /// ([`Features::encrypted_names`] only).
+    #[serde(default)]
+    pub(crate) name_key: Option<Entry>,
+    /// Present when [`Features::manifest`] is on; a journal, if any, carries the latest.
+    #[serde(default)]
+    pub(crate) integrity: Option<Integrity>,
+    /// With [`Features::key_slots`]; the header's KDF is then only the default for new
+    /// password slots.
+    #[serde(default)]
+    pub(crate) slots: Vec<KeySlot>,
+    /// Random key the entries are sealed under, sealed under the master key. Absent in
+    /// older stores, whose entries are sealed under the master key itself.
+    #[serde(default)]
+    pub(crate) data_key: Option<WrappedKey>,
+    /// Data keys from earlier epochs, while a rotation still has entries sealed under them.
+    #[serde(default)]
+    pub(crate) retired_keys: Vec<WrappedKey>,
 }
 
 pub(crate) fn now_secs() -> u64 {
@@ -62,81 +349,3 @@ pub(crate) fn now_secs() -> u64 {
         .map(|d| d.as_secs())
         .unwrap_or(0)
 }
-
-pub(crate) fn lock_path_for(path: &Path) -> PathBuf {
-    let mut s = path.as_os_str().to_os_string();
-    s.push(".lock");
-    PathBuf::from(s)
-}
-
-/// Lock the `.lock` sidecar; the returned handle must outlive the store. No-op for
-/// [`LockMode::None`].
-pub(crate) fn acquire_lock(path: &Path, mode: LockMode, read_only: bool) -> Result<Option<File>> {
-    if matches!(mode, LockMode::None) {
-        return Ok(None);
-    }
-    let lock_path = lock_path_for(path);
-    if let Some(parent) = lock_path.parent() {
-        if !parent.as_os_str().is_empty() && !parent.is_dir() {
-            fs::create_dir_all(parent)?;
-        }
-    }
-    let file = OpenOptions::new()
-        .read(true)
-        .write(true)
-        .create(true)
-        .truncate(false)
-        .open(&lock_path)?;
-
-    // std's native file locking (stable since 1.89). `try_lock` is the exclusive variant.
-    let result = if read_only || matches!(mode, LockMode::Shared) {
-        file.try_lock_shared()
-    } else {
-        file.try_lock()
-    };
-    result.map_err(|_| Error::Locked)?;
-    Ok(Some(file))
-}
-
-/// Write via temp file + fsync + rename + dir fsync, so a crash leaves either the old or
-/// the new complete file — never a torn one.
-pub(crate) fn atomic_write(path: &Path, bytes: &[u8]) -> Result<()> {
-    let dir = match path.parent() {
-        Some(p) if !p.as_os_str().is_empty() => p,
-        _ => Path::new("."),
-    };
-    if !dir.is_dir() {
-        fs::create_dir_all(dir)?;
-    }
-
-    let file_name = path
-        .file_name()
-        .ok_or_else(|| Error::CorruptStore("store path has no file name".to_string()))?;
-    let mut tmp_name = file_name.to_os_string();
-    tmp_name.push(format!(".tmp.{}.{:016x}", std::process::id(), rand_u64()?));
-    let tmp_path = dir.join(tmp_name);
-
-    let write_result = (|| -> Result<()> {
-        let mut file = OpenOptions::new()
-            .write(true)
-            .create_new(true)
-            .open(&tmp_path)?;
-        file.write_all(bytes)?;
-        file.sync_all()?;
-        Ok(())
-    })();
-    if let Err(e) = write_result {
-        let _ = fs::remove_file(&tmp_path);
-        return Err(e);
-    }
-
-    if let Err(e) = fs::rename(&tmp_path, path) {
-        let _ = fs::remove_file(&tmp_path);
-        return Err(e.into());
-    }
-
-    if let Ok(dir_file) = File::open(dir) {
-        let _ = dir_file.sync_all();
-    }
-    Ok(())
-}
@@ -0,0 +1,259 @@
+//! Lazy iteration over a namespace: [`Tree::iter`](crate::Tree::iter) and friends.
+//!
+//! Iterators snapshot the namespace's *keys* when created, then read each entry when they
+//! reach it, holding the store's lock only for that lookup. They are weakly consistent:
+//! each key present at creation and still present when reached is visited once, with its
+//! value as of that moment. Keys added later aren't visited, and keys removed (or expired)
+//! first are skipped. Writing to the store while iterating is fine.
+//!
+//! Keys come in lexicographic order, and every iterator also runs in reverse. In stores
+//! with encrypted names, buckets are ordered by blind index instead, so every iterator
+//! decrypts the names in the namespace up front to sort by: O(n) in the size of the
+//! namespace, however few keys it returns. That happens on a copy of the sealed entries,
+//! after the lock is released, so writers aren't held up meanwhile.
+
+use std::marker::PhantomData;
+use std::ops::{Bound, RangeBounds};
+use std::sync::Arc;
+
+use serde::de::DeserializeOwned;
+
+use crate::config::Codec;
+use crate::error::Result;
+use crate::format::Entry;
+use crate::store::{fetch, Frame, Inner};
+
+/// Walks a snapshot of one namespace's keys, decrypting entries as it reaches them.
+pub(crate) struct Cursor {
+    inner: Arc<Inner>,
+    ns_id: String,
+    codec: Codec,
+    keys: std::vec::IntoIter<String>,
+    prefix: String,
+}
+
+impl Cursor {
+    pub(crate) fn new(inner: Arc<Inner>, ns: &str, prefix: &str) -> Result<Self> {
+        let ns_id = inner.ns_id(ns)?;
+        let keys: Vec<String> = match inner.encrypted_names() {
+            false => {
+                let g = inner.read_store()?;
+                g.get(&ns_id)
+                    .map(|bucket| {
+                        bucket
+                            .keys()
+                            .filter(|k| k.starts_with(prefix))
+                            .cloned()
+                            .collect()
+                    })
+                    .unwrap_or_default()
+            }
+            // blind indexes can't be prefix-matched or sorted; names are decrypted for both
+            true => Self::sorted_names(&inner, &ns_id, |key| key.starts_with(prefix))?,
+        };
+        Ok(Cursor {
+            codec: inner.codec(&ns_id),
+            inner,
+            ns_id,
+            keys: keys.into_iter(),
+            prefix: prefix.to_string(),
+        })
+    }
+
+    /// Keys within `lo..hi`, in key order.
+    pub(crate) fn range(
+        inner: Arc<Inner>,
+        ns: &str,
+        lo: Bound<&str>,
+        hi: Bound<&str>,
+    ) -> Result<Self> {
+        let ns_id = inner.ns_id(ns)?;
+        let keys = match inner.encrypted_names() {
+            false => {
+                let g = inner.read_store()?;
+                g.get(&ns_id)
+                    .map(|bucket| {
+                        bucket
+                            .range::<str, _>((lo, hi))
+                            .map(|(k, _)| k.clone())
+                            .collect()
+                    })
+                    .unwrap_or_default()
+            }
+            true => Self::sorted_names(&inner, &ns_id, |key| {
+                RangeBounds::<str>::contains(&(lo, hi), key)
+            })?,
+        };
+        Ok(Cursor {
+            codec: inner.codec(&ns_id),
+            inner,
+            ns_id,
+            keys: keys.into_iter(),
+            prefix: String::new(),
+        })
+    }
+
+    /// Blind indexes of the live keys that `keep` accepts, in key order. The names are
+    /// sealed in the entries: those are copied out, and decrypted once the lock is released.
+    fn sorted_names(
+        inner: &Inner,
+        ns_id: &str,
+        keep: impl Fn(&str) -> bool,
+    ) -> Result<Vec<String>> {
+        let sealed: Vec<(String, Entry)> = {
+            let g = inner.read_store()?;
+            g.get(ns_id)
+                .map(|bucket| bucket.iter().map(|(k, e)| (k.clone(), e.clone())).collect())
+                .unwrap_or_default()
+        };
+        let mut named = Vec::new();
+        for (key_id, entry) in sealed {
+            let Some(frame) = inner.open_frame(ns_id, &key_id, &entry)? else {
+                continue;
+            };
+            let key = inner.key_name(&key_id, &frame)?;
+            if keep(&key) {
+                named.push((key, key_id));
+            }
+        }
+        named.sort();
+        Ok(named.into_iter().map(|(_, key_id)| key_id).collect())
+    }
+
+    /// The next live entry under the prefix, decrypted, from the front or the back.
+    fn advance(&mut self, back: bool) -> Option<Result<(String, Frame)>> {
+        loop {
+            let key_id = if back {
+                self.keys.next_back()?
+            } else {
+                self.keys.next()?
+            };
+            let entry = match self.inner.read_store() {
+                Ok(g) => fetch(&g, &self.ns_id, &key_id),
+                Err(e) => return Some(Err(e)),
+            };
+            let Some(entry) = entry else {
+                continue; // removed since the snapshot
+            };
+            let frame = match self.inner.open_frame(&self.ns_id, &key_id, &entry) {
+                Ok(Some(frame)) => frame,
+                Ok(None) => continue, // expired
+                Err(e) => return Some(Err(e)),
+            };
+            let key = match self.inner.key_name(&key_id, &frame) {
+                Ok(key) => key,
+                Err(e) => return Some(Err(e)),
+            };
+            if key.starts_with(&self.prefix) {
+                return Some(Ok((key, frame)));
+            }
+        }
+    }
+}
+
+/// The first (or, with `back`, last) live entry of a namespace in key order. With names
+/// in the clear this walks in from the edge of the bucket rather than snapshotting it.
+pub(crate) fn edge(inner: &Arc<Inner>, ns: &str, back: bool) -> Result<Option<(String, Frame)>> {
+    if inner.encrypted_names() {
+        let mut cursor = Cursor::range(Arc::clone(inner), ns, Bound::Unbounded, Bound::Unbounded)?;
+        return cursor.advance(back).transpose();
+    }
+    let ns_id = inner.ns_id(ns)?;
+    // past the expired entries seen so far
+    let mut past: Bound<String> = Bound::Unbounded;
+    loop {
+        let next = {
+            let g = inner.read_store()?;
+            let Some(bucket) = g.get(&ns_id) else {
+                return Ok(None);
+            };
+            let past = past.as_ref().map(String::as_str);
+            let item = if back {
+                bucket.range::<str, _>((Bound::Unbounded, past)).next_back()
+            } else {
+                bucket.range::<str, _>((past, Bound::Unbounded)).next()
+            };
+            item.map(|(k, e)| (k.clone(), e.clone()))
+        };
+        let Some((key_id, entry)) = next else {
+            return Ok(None);
+        };
+        if let Some(frame) = inner.open_frame(&ns_id, &key_id, &entry)? {
+            return Ok(Some((key_id, frame)));
+        }
+        past = Bound::Excluded(key_id);
+    }
+}
+
+/// Live entries of a namespace, decrypted one at a time; see [`Tree::iter`](crate::Tree::iter).
+/// An entry that fails to decrypt or deserialize yields an `Err`, and iteration can go on.
+pub struct Iter<V> {
+    cursor: Cursor,
+    _value: PhantomData<fn() -> V>,
+}
+
+impl<V> Iter<V> {
+    pub(crate) fn new(cursor: Cursor) -> Self {
+        Iter {
+            cursor,
+            _value: PhantomData,
+        }
+    }
+}
+
+impl<V: DeserializeOwned> Iterator for Iter<V> {
+    type Item = Result<(String, V)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let codec = self.cursor.codec;
+        Some(
+            self.cursor
+                .advance(false)?
+                .and_then(|next| decoded(codec, next)),
+        )
+    }
+}
+
+impl<V: DeserializeOwned> DoubleEndedIterator for Iter<V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let codec = self.cursor.codec;
+        Some(
+            self.cursor
+                .advance(true)?
+                .and_then(|next| decoded(codec, next)),
+        )
+    }
+}
+
+pub(crate) fn decoded<V: DeserializeOwned>(
+    codec: Codec,
+    (key, mut frame): (String, Frame),
+) -> Result<(String, V)> {
+    Ok((key, codec.decode(std::mem::take(&mut frame.value))?))
+}
+
+/// Live keys of a namespace; see [`Tree::iter_keys`](crate::Tree::iter_keys). Values are
+/// still decrypted (and wiped) along the way, since expiry is sealed inside them.
+pub struct Keys {
+    cursor: Cursor,
+}
+
+impl Keys {
+    pub(crate) fn new(cursor: Cursor) -> Self {
+        Keys { cursor }
+    }
+}
+
+impl Iterator for Keys {
+    type Item = Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.cursor.advance(false)?.map(|(key, _)| key))
+    }
+}
+
+impl DoubleEndedIterator for Keys {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        Some(self.cursor.advance(true)?.map(|(key, _)| key))
+    }
+}
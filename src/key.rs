@@ -0,0 +1,38 @@
+//! Byte keys. Key names are stored as text: a key that is UTF-8 (and doesn't start with
+//! NUL) is stored as itself, and any other key as NUL followed by its lowercase hex. Names
+//! that come back from iteration are in this form; [`key_bytes`] turns one back into the
+//! key.
+
+use std::borrow::Cow;
+
+const BYTES_MARK: char = '\0';
+
+/// The name a key is stored under.
+pub(crate) fn key_name(key: &[u8]) -> Cow<'_, str> {
+    match std::str::from_utf8(key) {
+        Ok(name) if !name.starts_with(BYTES_MARK) => Cow::Borrowed(name),
+        _ => {
+            let mut name = String::with_capacity(1 + 2 * key.len());
+            name.push(BYTES_MARK);
+            for b in key {
+                name.push_str(&format!("{b:02x}"));
+            }
+            Cow::Owned(name)
+        }
+    }
+}
+
+/// The key stored under `name`, as returned by [`Tree::keys`](crate::Tree::keys) and the
+/// iterators: its bytes for text keys, and the original bytes for binary ones.
+pub fn key_bytes(name: &str) -> Vec<u8> {
+    let is_hex = |hex: &str| {
+        hex.len().is_multiple_of(2) && hex.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+    };
+    match name.strip_prefix(BYTES_MARK) {
+        Some(hex) if is_hex(hex) => (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap_or_default())
+            .collect(),
+        _ => name.as_bytes().to_vec(),
+    }
+}
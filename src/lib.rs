@@ -2,7 +2,8 @@
 //! encrypted persistence to disk.
 //!
 //! Every store is encrypted, with values sealed with
-//! ChaCha20-Poly1305 under a key derived from a password (scrypt, or argon2 behind a
+//! ChaCha20-Poly1305 (or XChaCha20-Poly1305 / AES-256-GCM-SIV, see [`Cipher`]) under a
+//! key derived from a password (scrypt, or argon2 behind a
 //! feature flag) or supplied directly. Data is organized into isolated namespaces
 //! ("trees"), persisted atomically, and key material is held in memory-locked,
 //! auto-zeroed storage.
@@ -16,19 +17,42 @@
 //! assert_eq!(answer, 42);
 //! ```
 
+mod backend;
+mod background;
+mod backup;
 mod codec;
 mod config;
 mod crypto;
+#[cfg(feature = "json")]
+mod dump;
 mod error;
 mod format;
+mod iter;
+mod key;
+mod migrate;
 mod secret;
 mod store;
 mod tree;
 mod txn;
+mod typed;
+mod verify;
+mod wal;
+mod watch;
 
-pub use crate::config::{AutoSave, Config, Credential, KdfParams, LockMode};
+pub use crate::backend::{Backend, FileBackend, LockGuard};
+pub use crate::config::{
+    AutoSave, Cipher, Codec, Config, Credential, KdfParams, LockMode, OnConflict, Padding,
+    SlotInfo, SlotKind, SweepHook,
+};
+#[cfg(feature = "json")]
+pub use crate::dump::PlaintextAck;
 pub use crate::error::{Error, Result};
+pub use crate::iter::{Iter, Keys};
+pub use crate::key::key_bytes;
 pub use crate::secret::{Secret, SecretString};
 pub use crate::store::MicroKV;
 pub use crate::tree::Tree;
 pub use crate::txn::Txn;
+pub use crate::typed::{KeyCodec, TypedIter, TypedTree};
+pub use crate::verify::{BadEntry, LostEntries, VerifyReport};
+pub use crate::watch::{Event, EventKind};
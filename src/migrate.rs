@@ -0,0 +1,226 @@
+//! Upgrading stores written by older format versions.
+//!
+//! Versions this release reads:
+//!
+//! * v1 and v2 (before 0.3): no magic or version, and a layout of their own; see below.
+//! * v3 (0.3.0): one key, derived from the credential with the header's KDF and salt.
+//! * v4: adds the features header (encrypted names, cipher, padding, integrity tag).
+//! * v5: adds key slots wrapping a random master key.
+//! * v6: entries are sealed under a data key of their own, wrapped by the master key and
+//!   rotated in epochs, and values may use a codec other than msgpack ([`Config::codec`]).
+//!   Stores with neither are still written as v5, which older releases can read.
+//!
+//! Versions before v5 open read-only as they are. Writing needs the current layout, so a
+//! writable open upgrades the store first, with one atomic rewrite, unless
+//! [`Config::allow_upgrade`] is turned off. A store from before key slots sealed its
+//! entries under the key derived from its one credential, so the upgrade also gives it a
+//! random master key and data key and re-seals every entry: otherwise the old credential
+//! would keep opening them after it's changed. `tests/fixtures` holds a v3 store written by
+//! 0.3.0, and v4 stores written by the last commit before key slots (no release wrote v4);
+//! its README says how each was made.
+//!
+//! Stores written before 0.3 carry no magic or version: they're the store handle itself,
+//! in bincode 1.x's default encoding (little-endian, lengths as `u64`):
+//!
+//! * the store's path, as a string;
+//! * its entries: `{namespace: {key: value}}`, or `{key: value}` in the flat layout from
+//!   before namespaces;
+//! * one 24-byte nonce, as bytes;
+//! * from v2 on, the auto-commit flag, as a bool.
+//!
+//! Each value is bincode-encoded, then sealed with NaCl's `crypto_secretbox` under that
+//! nonce and the SHA-256 of the password (or the raw key). A file that doesn't decode as
+//! the current format is tried against each of these layouts, and taken to be the one it
+//! decodes as exactly. It's converted to the current format in memory, and unless opened
+//! read-only, written back with one atomic rewrite. Values are copied as they're encoded,
+//! so the store uses [`Codec::Bincode`](crate::Codec::Bincode), and opening one needs the
+//! `bincode` feature. Stores written without a password aren't read, as their values
+//! can't be told apart from ones sealed under another password.
+
+use sha2::{Digest, Sha256};
+use zeroize::Zeroizing;
+
+use crate::config::{Config, Credential, KdfRepr};
+use crate::crypto::{
+    header_aad, secretbox_open, SecretKey, KEY_LEN, SALT_LEN, SECRETBOX_NONCE_LEN,
+};
+use crate::error::{Error, Result};
+use crate::format::{
+    Entry, Features, KeySlot, FORMAT_VERSION, MIN_FORMAT_VERSION, MIN_WRITE_VERSION,
+};
+use crate::store::seal_verifier;
+
+/// The header fields an upgrade rewrites.
+pub(crate) struct Header {
+    pub(crate) verifier: Entry,
+    pub(crate) features: Vec<u8>,
+    pub(crate) slots: Vec<KeySlot>,
+    /// The slot the store was opened with.
+    pub(crate) active: Option<u32>,
+}
+
+/// Whether a store of `version` opens under `config`, and if so whether it must be
+/// upgraded first. Stores from before 0.3 don't get this far; see [`check_legacy`].
+pub(crate) fn check_version(version: u8, config: &Config) -> Result<bool> {
+    let readable = (MIN_FORMAT_VERSION..=FORMAT_VERSION).contains(&version);
+    let upgrade = version < MIN_WRITE_VERSION && !config.read_only;
+    if !readable || (upgrade && !config.allow_upgrade) {
+        return Err(Error::UnsupportedStoreVersion {
+            found: version,
+            expected: FORMAT_VERSION,
+        });
+    }
+    Ok(upgrade)
+}
+
+/// Bring the header of an older store up to [`MIN_WRITE_VERSION`], turning on the features
+/// every current store has. `master` is the key the store was opened with; a store from
+/// before key slots gets a random one in its place.
+pub(crate) fn upgrade(
+    header: &mut Header,
+    features: &mut Features,
+    cred: &Credential,
+    master: &mut SecretKey,
+    kdf: &KdfRepr,
+    salt: &[u8; SALT_LEN],
+) -> Result<()> {
+    // v3 -> v4: the missing features header already decoded as all defaults.
+
+    // v4 -> v5: a random master key, in a slot the credential's derived key opens. The
+    // derived key stays the data key for now; the caller retires it.
+    if !features.key_slots {
+        let derived = std::mem::replace(master, SecretKey::random()?);
+        header.slots = vec![KeySlot::seal_with(
+            0,
+            cred.kind(),
+            kdf.clone(),
+            *salt,
+            &derived,
+            master,
+        )?];
+        header.active = Some(0);
+    }
+    features.key_slots = true;
+    features.manifest = true;
+
+    header.features = features.encode()?;
+    let aad = header_aad(kdf, salt, &header.features, &header.slots)?;
+    header.verifier = seal_verifier(master, &aad)?;
+    Ok(())
+}
+
+/// [`check_version`] for a store from before 0.3, which must always be converted to be
+/// written.
+pub(crate) fn check_legacy(legacy: &Legacy, config: &Config) -> Result<()> {
+    if !config.read_only && !config.allow_upgrade {
+        return Err(Error::UnsupportedStoreVersion {
+            found: legacy.version,
+            expected: FORMAT_VERSION,
+        });
+    }
+    Ok(())
+}
+
+/// key -> sealed value, in file order.
+type Entries = Vec<(String, Vec<u8>)>;
+
+/// `(namespace, key, bincode-encoded value)`.
+type Opened<'a> = (&'a str, &'a str, Zeroizing<Vec<u8>>);
+
+/// A store from before 0.3, as read from its file.
+pub(crate) struct Legacy {
+    /// `1`, or `2` if the file ends with the auto-commit flag.
+    pub(crate) version: u8,
+    nonce: [u8; SECRETBOX_NONCE_LEN],
+    /// namespace -> key -> sealed value; the flat layout's entries are in `""`.
+    trees: Vec<(String, Entries)>,
+}
+
+impl Legacy {
+    /// Decode `raw` as whichever pre-0.3 layout it matches exactly, if any.
+    pub(crate) fn decode(raw: &[u8]) -> Option<Self> {
+        [(true, 2), (false, 2), (true, 1), (false, 1)]
+            .into_iter()
+            .find_map(|(nested, version)| Self::decode_as(raw, nested, version))
+    }
+
+    fn decode_as(raw: &[u8], nested: bool, version: u8) -> Option<Self> {
+        let mut rd = Bincode(raw);
+        rd.string()?;
+        let trees = if nested {
+            rd.map(|rd| Some((rd.string()?, rd.map(Bincode::entry)?)))?
+        } else {
+            vec![(String::new(), rd.map(Bincode::entry)?)]
+        };
+        let nonce = rd.bytes()?.try_into().ok()?;
+        if version >= 2 && rd.take(1)?[0] > 1 {
+            return None;
+        }
+        rd.0.is_empty().then_some(Legacy {
+            version,
+            nonce,
+            trees,
+        })
+    }
+
+    /// Every value opened with `cred`. Fails with [`Error::WrongPassword`] if any doesn't
+    /// open.
+    pub(crate) fn open(&self, cred: &Credential) -> Result<Vec<Opened<'_>>> {
+        let key = Zeroizing::new(match cred {
+            Credential::Password(pwd) => <[u8; KEY_LEN]>::from(Sha256::digest(pwd.as_bytes())),
+            Credential::Key(key) => *key,
+        });
+        let mut out = Vec::new();
+        for (ns, entries) in &self.trees {
+            for (k, sealed) in entries {
+                let value =
+                    secretbox_open(&key, &self.nonce, sealed).map_err(|_| Error::WrongPassword)?;
+                out.push((ns.as_str(), k.as_str(), value));
+            }
+        }
+        Ok(out)
+    }
+}
+
+/// A reader over bincode 1.x's default encoding; `None` wherever `raw` doesn't fit.
+struct Bincode<'a>(&'a [u8]);
+
+impl<'a> Bincode<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        if n > self.0.len() {
+            return None;
+        }
+        let (head, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Some(head)
+    }
+
+    fn len(&mut self) -> Option<usize> {
+        let len = u64::from_le_bytes(self.take(8)?.try_into().ok()?);
+        usize::try_from(len).ok()
+    }
+
+    fn bytes(&mut self) -> Option<Vec<u8>> {
+        let len = self.len()?;
+        self.take(len).map(<[u8]>::to_vec)
+    }
+
+    fn string(&mut self) -> Option<String> {
+        String::from_utf8(self.bytes()?).ok()
+    }
+
+    fn entry(&mut self) -> Option<(String, Vec<u8>)> {
+        Some((self.string()?, self.bytes()?))
+    }
+
+    /// A map, each of whose entries `f` reads. Lengths are checked as they're read, so a
+    /// bogus one fails rather than allocates.
+    fn map<T>(&mut self, f: impl Fn(&mut Self) -> Option<T>) -> Option<Vec<T>> {
+        let len = self.len()?;
+        let mut out = Vec::new();
+        for _ in 0..len {
+            out.push(f(self)?);
+        }
+        Some(out)
+    }
+}
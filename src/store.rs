@@ -1,48 +1,214 @@
 //! The database handle ([`MicroKV`]), its shared internal state, and the store-wide
 //! operations: opening (via [`Config`]), persistence, transactions, and key rotation.
 
-use std::fs::File;
-use std::path::{Path, PathBuf};
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Mutex, OnceLock, RwLock, RwLockReadGuard, RwLockWriteGuard};
 use std::time::{Duration, Instant};
 
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 use zeroize::Zeroize;
 
-use crate::codec::{decode, encode};
-use crate::config::{credential_key, derive_pwd, AutoSave, Config, Credential, KdfParams, KdfRepr};
-use crate::crypto::{aead_decrypt, aead_encrypt, gen_salt, header_aad, value_aad, SecretKey};
+use crate::backend::{Backend, FileBackend, LockGuard};
+use crate::background::{self, Worker};
+use crate::codec::decode_raw;
+use crate::config::{
+    credential_key, AutoSave, Cipher, Codec, Config, Credential, KdfParams, KdfRepr, LockMode,
+    Padding, SlotInfo,
+};
+use crate::crypto::{
+    aead_decrypt, aead_encrypt, blind_index, gen_salt, header_aad, value_aad, AeadCipher, SecretKey,
+};
 use crate::error::{Error, Result};
 use crate::format::{
-    acquire_lock, atomic_write, lock_path_for, now_secs, Entry, Store, StoreFile, StoreFileRef,
-    FORMAT_VERSION, MAGIC, VERIFIER_PLAINTEXT,
+    now_secs, Entry, Features, Integrity, KeySlot, Store, StoreFile, StoreFileRef, Tally,
+    WrappedKey, FORMAT_VERSION, MAGIC, MANIFEST_KEY_LABEL, NAME_KEY_AAD, VERIFIER_PLAINTEXT,
 };
-use crate::secret::SecretString;
+use crate::migrate::{self, Header, Legacy};
 use crate::tree::Tree;
 use crate::txn::Txn;
+use crate::typed::{KeyCodec, TypedTree};
+use crate::verify::{self, VerifyReport};
+use crate::wal::{self, Journal, LogOp, Replay, Wal};
+use crate::watch::{Event, EventKind, Target, Watchers};
 
-/// Crypto state behind its own lock, so `rekey` can swap it.
+/// Crypto state behind its own lock, so key changes can swap it.
+///
+/// Key hierarchy: each credential's slot wraps the master key, which authenticates the
+/// header and wraps the data key, which seals the entries. Changing credentials only
+/// re-wraps the master key; rotating the data key starts a new epoch, and entries move
+/// to it as they're rewritten or migrated by [`MicroKV::rotate_step`].
 struct Crypto {
+    /// The data key new entries are sealed under.
     key: SecretKey,
+    /// Epoch of `key`.
+    epoch: u32,
+    master: SecretKey,
+    /// `key`, sealed under `master`; `None` when they're the same key (older stores).
+    data_key: Option<WrappedKey>,
+    /// Keys of earlier epochs, until no entry is sealed under them.
+    retired: Vec<RetiredKey>,
+    /// What the data keys seal with (`Features::cipher`).
+    cipher: Cipher,
     kdf: KdfRepr,
     salt: [u8; crate::crypto::SALT_LEN],
     verifier: Entry,
+    /// Encoded [`Features`], exactly as authenticated by the verifier.
+    features: Vec<u8>,
+    /// The file version those features need ([`Features::version`]); see
+    /// [`Crypto::version`].
+    version: u8,
+    names: Option<NameKey>,
+    /// Empty for read-only stores from before key slots.
+    slots: Vec<KeySlot>,
+    /// The slot this handle was opened through, if any.
+    active: Option<u32>,
+}
+
+impl Crypto {
+    /// The version to write the store as. A reader from before data keys would open
+    /// entries with the master key, so a store with its own data key is always v6.
+    fn version(&self) -> u8 {
+        match self.data_key {
+            Some(_) => FORMAT_VERSION,
+            None => self.version,
+        }
+    }
+
+    /// Re-seal the verifier after a header change.
+    fn remint(&mut self) -> Result<()> {
+        let header = header_aad(&self.kdf, &self.salt, &self.features, &self.slots)?;
+        self.verifier = seal_verifier(&self.master, &header)?;
+        Ok(())
+    }
+
+    /// The cipher for entries sealed in `epoch`.
+    fn cipher_for(&self, epoch: u32) -> Result<AeadCipher> {
+        if epoch == self.epoch {
+            return self.key.cipher_as(self.cipher);
+        }
+        self.retired
+            .iter()
+            .find(|r| r.sealed.epoch == epoch)
+            .ok_or(Error::Crypto)?
+            .key
+            .cipher_as(self.cipher)
+    }
+
+    /// Switch to a fresh data key, wrapped under the master key, retiring the current one.
+    fn start_epoch(&mut self) -> Result<()> {
+        let new_key = SecretKey::random()?;
+        let epoch = self.epoch.checked_add(1).ok_or(Error::Crypto)?;
+        let data_key = WrappedKey::seal(epoch, &new_key, &self.master)?;
+        let retiring = WrappedKey::seal(self.epoch, &self.key, &self.master)?;
+        // the blind-index key stays, so entries keep their map keys; re-seal it
+        let names = match &self.names {
+            Some(names) => Some(NameKey::seal(names.key.try_clone()?, &new_key)?),
+            None => None,
+        };
+
+        let old_key = std::mem::replace(&mut self.key, new_key);
+        self.retired.push(RetiredKey {
+            key: old_key,
+            sealed: retiring,
+        });
+        self.epoch = epoch;
+        self.data_key = Some(data_key);
+        self.names = names;
+        Ok(())
+    }
+
+    /// The id of the slot `cred` opens.
+    fn slot_for(&self, cred: &Credential) -> Result<u32> {
+        for slot in &self.slots {
+            match slot.open(cred) {
+                Ok(_) => return Ok(slot.id),
+                Err(Error::WrongPassword) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Err(Error::WrongPassword)
+    }
+
+    /// Wrap the master key for `cred` into slot `id`, replacing any slot already there.
+    /// A new `kdf` is used for this slot and becomes the default for later ones.
+    fn put_slot(&mut self, id: u32, cred: &Credential, kdf: Option<KdfRepr>) -> Result<()> {
+        let slot = KeySlot::seal(id, cred, kdf.as_ref().unwrap_or(&self.kdf), &self.master)?;
+        match self.slots.iter_mut().find(|s| s.id == id) {
+            Some(existing) => *existing = slot,
+            None => self.slots.push(slot),
+        }
+        if let Some(kdf) = kdf {
+            self.kdf = kdf;
+        }
+        Ok(())
+    }
+
+    fn next_slot_id(&self) -> u32 {
+        self.slots.iter().map(|s| s.id + 1).max().unwrap_or(0)
+    }
+}
+
+/// Blind-index key for stores with encrypted names, plus its sealed form for the file.
+struct NameKey {
+    key: SecretKey,
+    sealed: Entry,
+}
+
+impl NameKey {
+    fn seal(key: SecretKey, under: &SecretKey) -> Result<Self> {
+        let sealed = Entry::new(key.wrap(&under.cipher(), NAME_KEY_AAD)?);
+        Ok(NameKey { key, sealed })
+    }
+}
+
+/// A data key from an earlier epoch, plus its sealed form for the file.
+struct RetiredKey {
+    key: SecretKey,
+    sealed: WrappedKey,
 }
 
 /// Shared, reference-counted store state. Every [`MicroKV`] clone points at one `Inner`.
 pub(crate) struct Inner {
     pub(crate) storage: RwLock<Store>,
     crypto: RwLock<Crypto>,
-    path: Option<PathBuf>,
+    /// `None` for in-memory stores.
+    backend: Option<Box<dyn Backend>>,
     autosave: AutoSave,
     read_only: bool,
+    /// Map keys are blind indexes rather than names (`Features::encrypted_names`).
+    encrypted_names: bool,
+    /// Applied when sealing (`Features::padding`).
+    padding: Padding,
+    /// Serializes values (`Features::codec`), except in namespaces listed in `codecs`.
+    codec: Codec,
+    /// Keyed by map key (`Features::namespace_codecs`).
+    codecs: BTreeMap<String, Codec>,
+    /// Saves carry an integrity tag (`Features::manifest`).
+    manifest: bool,
+    /// Bumped by every save; see [`MicroKV::generation`].
+    generation: AtomicU64,
     commit_lock: Mutex<()>,
     dirty: AtomicBool,
     last_save: Mutex<Instant>,
+    /// Persist by appending to the journal rather than rewriting (`Config::wal`).
+    journaled: bool,
+    journal: Mutex<Journal>,
+    wal: Mutex<Wal>,
+    /// Set by a [`Config::salvage`] open; see [`MicroKV::salvage_report`].
+    pub(crate) salvaged: OnceLock<VerifyReport>,
+    /// Change subscriptions; see [`Tree::watch`].
+    watchers: Mutex<Watchers>,
+    /// The background sweeper, if [`Config::sweep_interval`] started one.
+    pub(crate) sweeper: OnceLock<Worker>,
+    /// The autosave timer, for [`AutoSave::Periodic`] and [`AutoSave::Debounced`].
+    pub(crate) autosaver: OnceLock<Worker>,
     // held for the store's lifetime to keep the cross-process lock; never read.
-    _file_lock: Option<File>,
+    _lock: Option<LockGuard>,
 }
 
 /// The database handle. Cheap to clone (`Arc`-backed); all clones share one store.
@@ -67,7 +233,7 @@ impl std::ops::Deref for MicroKV {
 impl std::fmt::Debug for MicroKV {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("MicroKV")
-            .field("path", &self.inner.path)
+            .field("backend", &self.inner.backend)
             .field("read_only", &self.inner.read_only)
             .finish_non_exhaustive()
     }
@@ -99,7 +265,7 @@ impl MicroKV {
     /// Open, creating the store if it doesn't exist.
     pub fn open(path: impl AsRef<Path>, cred: Credential) -> Result<Self> {
         Self::build(
-            Some(to_path(path)),
+            Some(on_disk(path)),
             OpenMode::OpenOrCreate,
             cred,
             Config::default(),
@@ -108,13 +274,13 @@ impl MicroKV {
 
     /// [`MicroKV::open`] with explicit [`Config`].
     pub fn open_with(path: impl AsRef<Path>, cred: Credential, config: Config) -> Result<Self> {
-        Self::build(Some(to_path(path)), OpenMode::OpenOrCreate, cred, config)
+        Self::build(Some(on_disk(path)), OpenMode::OpenOrCreate, cred, config)
     }
 
     /// Fails if the store doesn't exist.
     pub fn open_existing(path: impl AsRef<Path>, cred: Credential) -> Result<Self> {
         Self::build(
-            Some(to_path(path)),
+            Some(on_disk(path)),
             OpenMode::MustExist,
             cred,
             Config::default(),
@@ -127,13 +293,13 @@ impl MicroKV {
         cred: Credential,
         config: Config,
     ) -> Result<Self> {
-        Self::build(Some(to_path(path)), OpenMode::MustExist, cred, config)
+        Self::build(Some(on_disk(path)), OpenMode::MustExist, cred, config)
     }
 
     /// Fails if the store already exists.
     pub fn create_new(path: impl AsRef<Path>, cred: Credential) -> Result<Self> {
         Self::build(
-            Some(to_path(path)),
+            Some(on_disk(path)),
             OpenMode::MustCreate,
             cred,
             Config::default(),
@@ -146,17 +312,52 @@ impl MicroKV {
         cred: Credential,
         config: Config,
     ) -> Result<Self> {
-        Self::build(Some(to_path(path)), OpenMode::MustCreate, cred, config)
+        Self::build(Some(on_disk(path)), OpenMode::MustCreate, cred, config)
+    }
+
+    /// [`MicroKV::open_with`] on a custom [`Backend`] rather than a file.
+    pub fn open_backend(
+        backend: impl Backend + 'static,
+        cred: Credential,
+        config: Config,
+    ) -> Result<Self> {
+        Self::build(
+            Some(Box::new(backend)),
+            OpenMode::OpenOrCreate,
+            cred,
+            config,
+        )
+    }
+
+    /// [`MicroKV::open_existing_with`] on a custom [`Backend`].
+    pub fn open_existing_backend(
+        backend: impl Backend + 'static,
+        cred: Credential,
+        config: Config,
+    ) -> Result<Self> {
+        Self::build(Some(Box::new(backend)), OpenMode::MustExist, cred, config)
+    }
+
+    /// [`MicroKV::create_new_with`] on a custom [`Backend`].
+    pub fn create_new_backend(
+        backend: impl Backend + 'static,
+        cred: Credential,
+        config: Config,
+    ) -> Result<Self> {
+        Self::build(Some(Box::new(backend)), OpenMode::MustCreate, cred, config)
     }
 
     /// Enforce the mode, then read or create.
     fn build(
-        path: Option<PathBuf>,
+        backend: Option<Box<dyn Backend>>,
         mode: OpenMode,
         cred: Credential,
         config: Config,
     ) -> Result<Self> {
-        let exists = path.as_ref().map(|p| p.is_file()).unwrap_or(false);
+        let exists = match &backend {
+            Some(backend) => backend.exists()?,
+            None => false,
+        };
 
         match mode {
             OpenMode::MustExist if !exists => {
@@ -169,40 +370,74 @@ impl MicroKV {
             _ => {}
         }
 
-        if exists {
-            Self::open_existing_file(path.expect("existing path implies Some"), cred, config)
-        } else {
-            Self::create(path, cred, config)
+        let lock = match &backend {
+            Some(backend) if !matches!(config.lock_mode, LockMode::None) => {
+                let exclusive =
+                    matches!(config.lock_mode, LockMode::Exclusive) && !config.read_only;
+                Some(backend.lock(exclusive)?)
+            }
+            _ => None,
+        };
+
+        match backend {
+            Some(backend) if exists => {
+                let raw = backend.load()?;
+                Self::load(&raw, Some(backend), cred, config, lock)
+            }
+            backend => Self::create(backend, &cred, config, lock),
         }
     }
 
-    fn open_existing_file(path: PathBuf, cred: Credential, config: Config) -> Result<Self> {
-        let file_lock = acquire_lock(&path, config.lock_mode, config.read_only)?;
+    /// Open a store from its encrypted bytes, e.g. as returned by [`MicroKV::export`],
+    /// with the same checks as opening a file. The result lives in memory: persist it with
+    /// [`MicroKV::save_as`] or [`MicroKV::export`].
+    pub fn from_bytes(bytes: &[u8], cred: Credential, config: Config) -> Result<Self> {
+        Self::load(bytes, None, cred, config, None)
+    }
 
-        let raw = std::fs::read(&path)?;
-        let sf: StoreFile = rmp_serde::from_slice(&raw)
-            .map_err(|e| Error::CorruptStore(format!("cannot deserialize store: {e}")))?;
+    /// [`MicroKV::from_bytes`], reading the bytes to the end of `reader`.
+    pub fn from_reader(mut reader: impl Read, cred: Credential, config: Config) -> Result<Self> {
+        let mut raw = Vec::new();
+        reader.read_to_end(&mut raw)?;
+        Self::from_bytes(&raw, cred, config)
+    }
+
+    /// Authenticate and decode a serialized store; with a backend, also replay its journal.
+    fn load(
+        raw: &[u8],
+        backend: Option<Box<dyn Backend>>,
+        cred: Credential,
+        config: Config,
+        lock: Option<LockGuard>,
+    ) -> Result<Self> {
+        // A salvage open reads what it can of a file that doesn't decode as a whole.
+        let (sf, lost) = match rmp_serde::from_slice::<StoreFile>(raw) {
+            Ok(sf) => (sf, None),
+            Err(e) => {
+                if let Some(legacy) = Legacy::decode(raw) {
+                    return Self::convert(legacy, backend, cred, config, lock);
+                }
+                config
+                    .salvage
+                    .then(|| verify::decode_damaged(raw))
+                    .flatten()
+                    .ok_or_else(|| Error::CorruptStore(format!("cannot deserialize store: {e}")))?
+            }
+        };
 
         if sf.magic != MAGIC {
             return Err(Error::CorruptStore(
                 "not a microkv store (bad magic)".to_string(),
             ));
         }
-        if sf.version != FORMAT_VERSION {
-            return Err(Error::UnsupportedStoreVersion {
-                found: sf.version,
-                expected: FORMAT_VERSION,
-            });
-        }
+        let upgrade = migrate::check_version(sf.version, &config)?;
 
-        let mut key_bytes = credential_key(&cred, &sf.kdf, &sf.salt)?;
-        let secret = SecretKey::new(key_bytes)?;
-        key_bytes.zeroize();
+        let (mut master, active) = unlock(&cred, &sf)?;
 
         // Verify the credential (and authenticate the header) via the verifier.
-        let header = header_aad(&sf.kdf, &sf.salt)?;
+        let header = header_aad(&sf.kdf, &sf.salt, &sf.features, &sf.slots)?;
         let plaintext = aead_decrypt(
-            &secret.cipher(),
+            &master.cipher(),
             &header,
             &sf.verifier.nonce,
             &sf.verifier.data,
@@ -212,63 +447,334 @@ impl MicroKV {
             return Err(Error::WrongPassword);
         }
 
-        Ok(MicroKV::from_inner(Arc::new(Inner {
-            storage: RwLock::new(sf.trees),
+        let (secret, epoch) = match &sf.data_key {
+            Some(sealed) => (sealed.open(&master)?, sealed.epoch),
+            None => (master.try_clone()?, 0),
+        };
+        let retired = sf
+            .retired_keys
+            .into_iter()
+            .map(|sealed| {
+                Ok(RetiredKey {
+                    key: sealed.open(&master)?,
+                    sealed,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut features = Features::decode(&sf.features)?;
+        let names = if features.encrypted_names {
+            let sealed = sf
+                .name_key
+                .ok_or_else(|| Error::CorruptStore("missing name key".to_string()))?;
+            let key =
+                SecretKey::unwrap(&secret.cipher(), NAME_KEY_AAD, &sealed.nonce, &sealed.data)?;
+            Some(NameKey { key, sealed })
+        } else {
+            None
+        };
+
+        // Fold in writes journaled since the base file was last rewritten.
+        let mut trees = sf.trees;
+        let cipher = secret.cipher_as(features.cipher)?;
+        features.codec.check()?;
+        for codec in features.namespace_codecs.values() {
+            codec.check()?;
+        }
+        let replay = match &backend {
+            Some(backend) => wal::replay(
+                backend.as_ref(),
+                sf.log_id,
+                &cipher,
+                &mut trees,
+                config.salvage,
+            )?,
+            None => Replay::default(),
+        };
+        let wal = Wal {
+            log_id: sf.log_id,
+            seq: replay.seq,
+            len: replay.len,
+            base_len: raw.len() as u64,
+        };
+
+        // Check the set of entries as a whole (a salvage open notes a mismatch and carries
+        // on), then its freshness.
+        let manifest = match features.manifest {
+            true => {
+                let key = secret.derive(MANIFEST_KEY_LABEL)?;
+                let tally = Tally::of(&key, &trees);
+                Some((key, tally))
+            }
+            false => None,
+        };
+        let integrity = replay.integrity.or(sf.integrity);
+        let (generation, intact) = match (&manifest, integrity) {
+            (Some((key, tally)), Some(found)) => (
+                found.generation,
+                found.matches(&tally.seal(key, found.generation)),
+            ),
+            (Some(_), None) => (0, false),
+            (None, _) => (0, true),
+        };
+        if !intact && !config.salvage {
+            return Err(Error::Integrity);
+        }
+        if generation < config.min_generation {
+            return Err(Error::Rollback {
+                found: generation,
+                expected: config.min_generation,
+            });
+        }
+
+        let mut header = Header {
+            verifier: sf.verifier,
+            features: sf.features,
+            slots: sf.slots,
+            active,
+        };
+        // the data key of a store from before key slots is derived from its credential
+        let derived = upgrade && !features.key_slots;
+        if upgrade {
+            migrate::upgrade(
+                &mut header,
+                &mut features,
+                &cred,
+                &mut master,
+                &sf.kdf,
+                &sf.salt,
+            )?;
+        }
+
+        let db = MicroKV::from_inner(Arc::new(Inner {
+            storage: RwLock::new(trees),
             crypto: RwLock::new(Crypto {
                 key: secret,
+                epoch,
+                master,
+                data_key: sf.data_key,
+                retired,
+                cipher: features.cipher,
                 kdf: sf.kdf,
                 salt: sf.salt,
-                verifier: sf.verifier,
+                verifier: header.verifier,
+                features: header.features,
+                version: features.version(),
+                names,
+                slots: header.slots,
+                active: header.active,
             }),
-            path: Some(path),
+            journaled: config.wal && backend.as_ref().is_some_and(|b| b.supports_journal()),
+            backend,
             autosave: config.autosave,
             read_only: config.read_only,
+            encrypted_names: features.encrypted_names,
+            padding: features.padding,
+            codec: features.codec,
+            codecs: features.namespace_codecs,
+            manifest: features.manifest,
+            generation: AtomicU64::new(generation),
             commit_lock: Mutex::new(()),
             dirty: AtomicBool::new(false),
             last_save: Mutex::new(Instant::now()),
-            _file_lock: file_lock,
-        })))
+            journal: Mutex::new(Journal {
+                rewrite: upgrade,
+                tally: manifest.filter(|_| !upgrade).map(|(_, tally)| tally),
+                ..Default::default()
+            }),
+            wal: Mutex::new(wal),
+            salvaged: OnceLock::new(),
+            watchers: Mutex::new(Watchers::default()),
+            sweeper: OnceLock::new(),
+            autosaver: OnceLock::new(),
+            _lock: lock,
+        }));
+
+        // Set aside entries that fail authentication, so the rest of the store is usable.
+        if config.salvage {
+            let mut report = {
+                let mut store = db.inner.write_store()?;
+                let report = verify::check(&db.inner, &store);
+                for bad in &report.bad {
+                    if let Some(bucket) = store.get_mut(&bad.namespace) {
+                        bucket.remove(&bad.key);
+                    }
+                }
+                store.retain(|_, bucket| !bucket.is_empty());
+                report
+            };
+            report.integrity_failed = !intact;
+            report.journal_truncated = replay.truncated;
+            report.lost = lost;
+            if !report.is_clean() {
+                db.inner.request_rewrite();
+            }
+            let _ = db.inner.salvaged.set(report);
+        }
+
+        // Move the entries off a derived data key, so the credential it came from opens
+        // nothing once it's changed. Then write the upgraded store out straight away, so
+        // it's never left half-migrated.
+        if derived {
+            db.inner
+                .crypto
+                .write()
+                .map_err(|_| Error::Locked)?
+                .start_epoch()?;
+            db.inner.reseal(usize::MAX)?;
+        }
+        if upgrade && db.inner.backend.is_some() {
+            db.inner.persist()?;
+        }
+        background::start_sweeper(&db.inner, config.sweep_interval, config.on_sweep);
+        background::start_autosaver(&db.inner, config.autosave);
+        Ok(db)
+    }
+
+    /// Rebuild a store from before 0.3 in the current format (see [`migrate`]), and unless
+    /// it's opened read-only, write it back in one atomic rewrite.
+    fn convert(
+        legacy: Legacy,
+        backend: Option<Box<dyn Backend>>,
+        cred: Credential,
+        config: Config,
+        lock: Option<LockGuard>,
+    ) -> Result<Self> {
+        migrate::check_legacy(&legacy, &config)?;
+        let values = legacy.open(&cred)?;
+
+        // built in memory with the caller's settings, but the codec the values are in
+        let fresh = Config {
+            autosave: AutoSave::Manual,
+            read_only: false,
+            codec: Codec::Bincode,
+            namespace_codecs: BTreeMap::new(),
+            sweep_interval: None,
+            on_sweep: None,
+            ..config.clone()
+        };
+        let db = Self::create(None, &cred, fresh, None)?;
+        {
+            let mut store = db.inner.write_store()?;
+            for (ns, key, value) in values {
+                seal_encoded_into(&db.inner, &mut store, ns, key, &value, None)?;
+            }
+        }
+        let bytes = db.export()?;
+        drop(db);
+
+        if let Some(backend) = backend.as_deref().filter(|_| !config.read_only) {
+            backend.store(&bytes)?;
+        }
+        Self::load(&bytes, backend, cred, config, lock)
     }
 
-    fn create(path: Option<PathBuf>, cred: Credential, config: Config) -> Result<Self> {
+    fn create(
+        backend: Option<Box<dyn Backend>>,
+        cred: &Credential,
+        config: Config,
+        lock: Option<LockGuard>,
+    ) -> Result<Self> {
         let kdf = config.kdf.0.clone();
         let salt = gen_salt()?;
 
-        let mut key_bytes = credential_key(&cred, &kdf, &salt)?;
-        let secret = SecretKey::new(key_bytes)?;
-        key_bytes.zeroize();
+        // Random master and data keys; the master key is wrapped for the credential in
+        // the first slot.
+        let master = SecretKey::random()?;
+        let slots = vec![KeySlot::seal(0, cred, &kdf, &master)?];
+        let secret = SecretKey::random()?;
+        let data_key = WrappedKey::seal(0, &secret, &master)?;
+
+        // fail now, not at the first write, if the cipher or a codec isn't compiled in
+        secret.cipher_as(config.cipher)?;
+        config.codec.check()?;
+        for codec in config.namespace_codecs.values() {
+            codec.check()?;
+        }
+        let names = if config.encrypt_names {
+            Some(NameKey::seal(SecretKey::random()?, &secret)?)
+        } else {
+            None
+        };
+        // padding is looked up by map key, so record blind indexes rather than names
+        let padding = match (config.padding, &names) {
+            (Padding::Namespaces(sizes), Some(names)) => Padding::Namespaces(
+                sizes
+                    .into_iter()
+                    .map(|(ns, n)| (blind_index(&names.key, b"ns", &[&ns]), n))
+                    .collect(),
+            ),
+            (padding, _) => padding,
+        };
+        let namespace_codecs = match &names {
+            Some(names) => config
+                .namespace_codecs
+                .into_iter()
+                .map(|(ns, codec)| (blind_index(&names.key, b"ns", &[&ns]), codec))
+                .collect(),
+            None => config.namespace_codecs,
+        };
+        let features = Features {
+            encrypted_names: config.encrypt_names,
+            cipher: config.cipher,
+            padding,
+            manifest: true,
+            key_slots: true,
+            codec: config.codec,
+            namespace_codecs,
+        };
+        let features_raw = features.encode()?;
 
         // Mint the verifier, binding the header into its associated data.
-        let header = header_aad(&kdf, &salt)?;
-        let (nonce, data) = aead_encrypt(&secret.cipher(), &header, VERIFIER_PLAINTEXT)?;
-        let verifier = Entry { nonce, data };
-
-        let file_lock = match &path {
-            Some(p) => acquire_lock(p, config.lock_mode, config.read_only)?,
-            None => None,
-        };
+        let header = header_aad(&kdf, &salt, &features_raw, &slots)?;
+        let verifier = seal_verifier(&master, &header)?;
 
         let db = MicroKV::from_inner(Arc::new(Inner {
             storage: RwLock::new(Store::new()),
             crypto: RwLock::new(Crypto {
                 key: secret,
+                epoch: 0,
+                master,
+                data_key: Some(data_key),
+                retired: Vec::new(),
+                cipher: config.cipher,
                 kdf,
                 salt,
                 verifier,
+                features: features_raw,
+                version: features.version(),
+                names,
+                slots,
+                active: Some(0),
             }),
-            path,
+            journaled: config.wal && backend.as_ref().is_some_and(|b| b.supports_journal()),
+            backend,
             autosave: config.autosave,
             read_only: config.read_only,
+            encrypted_names: features.encrypted_names,
+            padding: features.padding,
+            codec: features.codec,
+            codecs: features.namespace_codecs,
+            manifest: features.manifest,
+            generation: AtomicU64::new(0),
             commit_lock: Mutex::new(()),
             dirty: AtomicBool::new(false),
             last_save: Mutex::new(Instant::now()),
-            _file_lock: file_lock,
+            journal: Mutex::new(Journal::default()),
+            wal: Mutex::new(Wal::default()),
+            salvaged: OnceLock::new(),
+            watchers: Mutex::new(Watchers::default()),
+            sweeper: OnceLock::new(),
+            autosaver: OnceLock::new(),
+            _lock: lock,
         }));
 
         // Materialize a new store on disk immediately so the header exists.
-        if db.inner.path.is_some() && !config.read_only {
+        if db.inner.backend.is_some() && !config.read_only {
             db.inner.persist()?;
         }
+        background::start_sweeper(&db.inner, config.sweep_interval, config.on_sweep);
+        background::start_autosaver(&db.inner, config.autosave);
 
         Ok(db)
     }
@@ -281,10 +787,33 @@ impl MicroKV {
         Tree::new(Arc::clone(&self.inner), name.as_ref().to_string())
     }
 
-    /// Namespaces that currently hold data.
+    /// [`MicroKV::namespace`], bound to one key type and one value type.
+    pub fn typed_namespace<K, V>(&self, name: impl AsRef<str>) -> TypedTree<K, V>
+    where
+        K: KeyCodec,
+        V: Serialize + DeserializeOwned,
+    {
+        TypedTree::new(self.namespace(name))
+    }
+
+    /// Namespaces that currently hold data. With encrypted names, each name is recovered by
+    /// decrypting one of the namespace's entries.
     pub fn tree_names(&self) -> Result<Vec<String>> {
         let g = self.inner.read_store()?;
-        Ok(g.keys().cloned().collect())
+        if !self.inner.encrypted_names {
+            return Ok(g.keys().cloned().collect());
+        }
+        let mut out = Vec::with_capacity(g.len());
+        for (ns_id, bucket) in g.iter() {
+            if let Some((key_id, entry)) = bucket.first_key_value() {
+                let frame = self.inner.unseal(ns_id, key_id, entry)?;
+                match &frame.names {
+                    Some((ns, _)) => out.push(ns.clone()),
+                    None => return Err(Error::Crypto),
+                }
+            }
+        }
+        Ok(out)
     }
 
     /* ============================ Transactions ============================ */
@@ -296,24 +825,41 @@ impl MicroKV {
         F: FnOnce(&mut Txn) -> Result<R>,
     {
         self.inner.ensure_writable()?;
+        let result = self.on_working_copy(|working| f(&mut Txn::new(working, self)))?;
+        self.inner.after_write()?;
+        Ok(result)
+    }
 
+    /// Run `f` under one write lock against a working copy of the store, and swap it in
+    /// if `f` succeeds. If it fails, the store is left unchanged and the events `f` recorded
+    /// are dropped. Callers follow a success with [`Inner::after_write`].
+    pub(crate) fn on_working_copy<R>(&self, f: impl FnOnce(&mut Store) -> Result<R>) -> Result<R> {
         let mut guard = self.inner.write_store()?;
+        let mark = self.inner.events_mark();
         let mut working = guard.clone();
-        let mut txn = Txn::new(&mut working, self);
 
-        match f(&mut txn) {
+        match f(&mut working) {
             Ok(result) => {
                 *guard = working;
-                drop(guard);
-                self.inner.after_write()?;
                 Ok(result)
             }
-            Err(e) => Err(e), // working discarded; guard left unchanged
+            Err(e) => {
+                // working discarded; guard left unchanged, and nobody hears of it
+                self.inner.drop_events_since(mark);
+                Err(e)
+            }
         }
     }
 
     /* ============================ Security / admin ============================ */
 
+    /// Save counter, authenticated by the store's integrity tag. Record it and pass it back
+    /// as [`Config::min_generation`] to detect a store rolled back to an older copy.
+    pub fn generation(&self) -> u64 {
+        self.inner.generation.load(Ordering::Acquire)
+    }
+
+    /// The KDF new password slots are derived with.
     pub fn kdf_params(&self) -> KdfParams {
         self.inner
             .crypto
@@ -322,71 +868,209 @@ impl MicroKV {
             .unwrap_or_else(|_| KdfParams::interactive())
     }
 
-    /// Verify `old`, then re-key everything under `new`.
+    /// Verify `old` against the store's password slots, then replace that slot's password
+    /// with `new`.
+    ///
+    /// This doesn't revoke `old`: a copy of the file from before the change still opens with
+    /// it, and that copy's master key unwraps everything written since. If `old` may have
+    /// leaked, follow with [`MicroKV::rotate_master_key`].
     pub fn change_password(&self, old: impl Into<String>, new: impl Into<String>) -> Result<()> {
-        let old = SecretString::new(old.into());
-        {
+        self.replace_password(old.into(), new.into(), None)
+    }
+
+    /// [`MicroKV::change_password`], deriving the new password's slot under `kdf`, which
+    /// also becomes [`MicroKV::kdf_params`].
+    pub fn change_password_with_kdf(
+        &self,
+        old: impl Into<String>,
+        new: impl Into<String>,
+        kdf: KdfParams,
+    ) -> Result<()> {
+        self.replace_password(old.into(), new.into(), Some(kdf.0))
+    }
+
+    fn replace_password(&self, old: String, new: String, kdf: Option<KdfRepr>) -> Result<()> {
+        self.inner.ensure_writable()?;
+        let old = Credential::password(old);
+        let id = {
             let c = self.inner.crypto.read().map_err(|_| Error::Locked)?;
-            let mut probe = derive_pwd(old.as_bytes(), &c.kdf, &c.salt)?;
-            let probe_key = SecretKey::new(probe)?;
-            probe.zeroize();
-            let header = header_aad(&c.kdf, &c.salt)?;
-            let ok = aead_decrypt(
-                &probe_key.cipher(),
-                &header,
-                &c.verifier.nonce,
-                &c.verifier.data,
-            )
-            .map(|p| p == VERIFIER_PLAINTEXT)
-            .unwrap_or(false);
-            if !ok {
-                return Err(Error::WrongPassword);
-            }
-        }
-        self.rekey(Credential::Password(SecretString::new(new.into())))
+            c.slot_for(&old)?
+        };
+        let new = Credential::password(new);
+        self.inner.update_slots(|c| c.put_slot(id, &new, kdf))
     }
 
-    /// Re-derive the key from `new` (fresh salt) and re-encrypt every entry + the verifier.
+    /// Replace the key slot this handle was opened through with one for `new`. Only the
+    /// master key is re-wrapped: the data key, and so every entry, stays as it is.
+    ///
+    /// This doesn't revoke the old credential: a copy of the file from before the change
+    /// still opens with it, and that copy's master key unwraps everything written since. If
+    /// the old credential may have leaked, follow with [`MicroKV::rotate_master_key`].
     pub fn rekey(&self, new: Credential) -> Result<()> {
+        self.replace_active(new, None)
+    }
+
+    /// [`MicroKV::rekey`], deriving the new slot under `kdf` (e.g. a higher cost, or
+    /// argon2id), which also becomes [`MicroKV::kdf_params`].
+    pub fn rekey_with(&self, new: Credential, kdf: KdfParams) -> Result<()> {
+        self.replace_active(new, Some(kdf.0))
+    }
+
+    fn replace_active(&self, new: Credential, kdf: Option<KdfRepr>) -> Result<()> {
+        self.inner.update_slots(|c| {
+            let id = c.active.unwrap_or_else(|| c.next_slot_id());
+            c.put_slot(id, &new, kdf)?;
+            c.active = Some(id);
+            Ok(())
+        })
+    }
+
+    /// Re-derive the slot `cred` opens under `kdf`, keeping the credential itself: moves a
+    /// store made with a cheap KDF to a stronger one. `kdf` also becomes
+    /// [`MicroKV::kdf_params`]. Fails with [`Error::WrongPassword`] if `cred` opens no slot.
+    pub fn upgrade_kdf(&self, cred: Credential, kdf: KdfParams) -> Result<()> {
         self.inner.ensure_writable()?;
-        let new_salt = gen_salt()?;
+        let id = {
+            let c = self.inner.crypto.read().map_err(|_| Error::Locked)?;
+            c.slot_for(&cred)?
+        };
+        self.inner
+            .update_slots(|c| c.put_slot(id, &cred, Some(kdf.0)))
+    }
 
-        {
-            let mut sg = self.inner.storage.write().map_err(|_| Error::Locked)?;
+    /// Add a key slot for `cred`, which can then open the store on its own. Returns the
+    /// new slot's id.
+    pub fn add_credential(&self, cred: Credential) -> Result<u32> {
+        self.inner.update_slots(|c| {
+            let id = c.next_slot_id();
+            c.put_slot(id, &cred, None)?;
+            Ok(id)
+        })
+    }
+
+    /// Remove key slot `id`, so its credential no longer opens the store. Fails with
+    /// [`Error::LastSlot`] rather than lock everyone out.
+    ///
+    /// This doesn't revoke the credential: a copy of the file from before the removal still
+    /// opens with it, and that copy's master key unwraps everything written since. If it
+    /// may have leaked, follow with [`MicroKV::rotate_master_key`].
+    pub fn remove_credential(&self, id: u32) -> Result<()> {
+        self.inner.update_slots(|c| {
+            let pos = c
+                .slots
+                .iter()
+                .position(|s| s.id == id)
+                .ok_or(Error::NoSuchSlot(id))?;
+            if c.slots.len() == 1 {
+                return Err(Error::LastSlot);
+            }
+            c.slots.remove(pos);
+            if c.active == Some(id) {
+                c.active = None;
+            }
+            Ok(())
+        })
+    }
+
+    /// The store's key slots; credentials themselves are never exposed.
+    pub fn list_slots(&self) -> Result<Vec<SlotInfo>> {
+        let c = self.inner.crypto.read().map_err(|_| Error::Locked)?;
+        Ok(c.slots
+            .iter()
+            .map(|s| SlotInfo {
+                id: s.id,
+                kind: s.kind,
+                active: c.active == Some(s.id),
+            })
+            .collect())
+    }
+
+    /// Re-encrypt every entry under a fresh random data key, e.g. if the old one may have
+    /// leaked. The new key is wrapped under the same master key, so this doesn't lock out a
+    /// removed credential; see [`MicroKV::rotate_master_key`]. Unlike credential changes
+    /// this touches every entry, under the write lock;
+    /// [`MicroKV::begin_data_key_rotation`] spreads the work out instead.
+    pub fn rotate_data_key(&self) -> Result<()> {
+        self.begin_data_key_rotation()?;
+        self.rotate_step(usize::MAX)?;
+        Ok(())
+    }
+
+    /// Switch to a fresh random data key without re-encrypting anything yet: new writes use
+    /// it, entries under older keys stay readable, and [`MicroKV::rotate_step`] migrates
+    /// them in batches. Returns how many entries are left to migrate.
+    pub fn begin_data_key_rotation(&self) -> Result<usize> {
+        self.inner.ensure_writable()?;
+        let remaining = {
+            let sg = self.inner.read_store()?;
             let mut cg = self.inner.crypto.write().map_err(|_| Error::Locked)?;
+            cg.start_epoch()?;
+            // the header changed, which only a full rewrite can persist
+            self.inner.request_rewrite();
 
-            let new_kdf = cg.kdf.clone();
-            let mut key_bytes = credential_key(&new, &new_kdf, &new_salt)?;
-            let new_secret = SecretKey::new(key_bytes)?;
-            key_bytes.zeroize();
-
-            let old_cipher = cg.key.cipher();
-            let new_cipher = new_secret.cipher();
-
-            for (ns, bucket) in sg.iter_mut() {
-                for (key, entry) in bucket.iter_mut() {
-                    let aad = value_aad(ns, key);
-                    let mut pt = aead_decrypt(&old_cipher, &aad, &entry.nonce, &entry.data)?;
-                    let (nonce, data) = aead_encrypt(&new_cipher, &aad, &pt)?;
-                    pt.zeroize();
-                    entry.nonce = nonce;
-                    entry.data = data;
-                }
+            sg.values().map(|bucket| bucket.len()).sum()
+        };
+
+        self.inner.after_write()?;
+        Ok(remaining)
+    }
+
+    /// Replace the master key, e.g. after [`MicroKV::remove_credential`] revoked a
+    /// credential that may have been compromised, and start a data key rotation as
+    /// [`MicroKV::begin_data_key_rotation`] does. Returns how many entries are left to
+    /// migrate.
+    ///
+    /// Removing a slot only stops the credential opening the *current* file: with an older
+    /// copy it still recovers the old master key, and from it any data key that master key
+    /// wraps. After this, values written (or migrated by [`MicroKV::rotate_step`]) are out
+    /// of its reach. Every remaining slot is re-wrapped for the new master key, so `creds`
+    /// must open each of them; [`Error::MissingCredential`] names the first that none
+    /// does, and nothing changes.
+    ///
+    /// The blind-index key of a store with encrypted names is kept, since the entries are
+    /// stored under the indexes it computes: the old credential can still tell whether a
+    /// name it guesses is in the store, though not read its value.
+    pub fn rotate_master_key(&self, creds: &[Credential]) -> Result<usize> {
+        self.inner.ensure_writable()?;
+        let remaining = {
+            let sg = self.inner.read_store()?;
+            let mut cg = self.inner.crypto.write().map_err(|_| Error::Locked)?;
+
+            let master = SecretKey::random()?;
+            let mut slots = Vec::with_capacity(cg.slots.len());
+            for slot in &cg.slots {
+                let cred = creds
+                    .iter()
+                    .find(|cred| slot.open(cred).is_ok())
+                    .ok_or(Error::MissingCredential(slot.id))?;
+                slots.push(KeySlot::seal(slot.id, cred, &slot.kdf, &master)?);
+            }
+            for retired in &mut cg.retired {
+                retired.sealed = WrappedKey::seal(retired.sealed.epoch, &retired.key, &master)?;
             }
+            cg.master = master;
+            cg.slots = slots;
+            // the data key is wrapped under the old master key in every older copy
+            cg.start_epoch()?;
+            cg.remint()?;
+            self.inner.request_rewrite();
+
+            sg.values().map(|bucket| bucket.len()).sum()
+        };
 
-            let header = header_aad(&new_kdf, &new_salt)?;
-            let (vn, vd) = aead_encrypt(&new_cipher, &header, VERIFIER_PLAINTEXT)?;
+        self.inner.after_write()?;
+        Ok(remaining)
+    }
 
-            cg.key = new_secret;
-            cg.salt = new_salt;
-            cg.kdf = new_kdf;
-            cg.verifier = Entry {
-                nonce: vn,
-                data: vd,
-            };
+    /// Re-encrypt up to `n` entries still sealed under an older data key. Once none are
+    /// left, the old keys are dropped. Returns how many entries are left to migrate.
+    pub fn rotate_step(&self, n: usize) -> Result<usize> {
+        self.inner.ensure_writable()?;
+        let (changed, remaining) = self.inner.reseal(n)?;
+        if changed {
+            self.inner.after_write()?;
         }
-
-        self.inner.after_write()
+        Ok(remaining)
     }
 
     /* ============================ Persistence ============================ */
@@ -396,10 +1080,18 @@ impl MicroKV {
         self.inner.save()
     }
 
+    /// Rewrite the store file in full, folding in (and deleting) its write-ahead log.
+    /// Equivalent to [`MicroKV::save`] for stores without [`Config::wal`].
+    pub fn compact(&self) -> Result<()> {
+        self.inner.ensure_writable()?;
+        self.inner.request_rewrite();
+        self.inner.save()
+    }
+
     /// Persist a copy elsewhere, leaving the store's own path unchanged.
     pub fn save_as(&self, path: impl AsRef<Path>) -> Result<()> {
         let bytes = self.inner.serialize()?;
-        atomic_write(path.as_ref(), &bytes)
+        FileBackend::new(path).store(&bytes)
     }
 
     /// The encrypted store as bytes (no filesystem access).
@@ -407,21 +1099,15 @@ impl MicroKV {
         self.inner.serialize()
     }
 
-    /// Clear all data and delete the file + its `.lock` sidecar.
+    /// Clear all data and delete the file + its `.wal` and `.lock` sidecars.
     pub fn destroy(self) -> Result<()> {
         self.inner.ensure_writable()?;
         {
             let mut g = self.inner.write_store()?;
             g.clear();
         }
-        if let Some(path) = &self.inner.path {
-            if path.exists() {
-                std::fs::remove_file(path)?;
-            }
-            let lock = lock_path_for(path);
-            if lock.exists() {
-                let _ = std::fs::remove_file(lock);
-            }
+        if let Some(backend) = &self.inner.backend {
+            backend.remove()?;
         }
         Ok(())
     }
@@ -430,32 +1116,42 @@ impl MicroKV {
     /// their (encrypted) expiry is trusted, so a tampered expiry errors instead of
     /// dropping a live value.
     pub fn sweep_expired(&self) -> Result<usize> {
-        self.inner.ensure_writable()?;
+        self.inner.sweep_expired()
+    }
+}
+
+impl Inner {
+    /// See [`MicroKV::sweep_expired`]; also run by the sweeper ([`Config::sweep_interval`]).
+    pub(crate) fn sweep_expired(&self) -> Result<usize> {
+        self.ensure_writable()?;
         let removed = {
-            let mut g = self.inner.write_store()?;
-            let mut stale: Vec<(String, String)> = Vec::new();
+            let mut g = self.write_store()?;
+            // names are only worked out (decrypted, if need be) for watchers
+            let watched = self.watched();
+            let mut stale = Vec::new();
             for (ns, bucket) in g.iter() {
                 for (key, entry) in bucket.iter() {
-                    if !self.inner.is_live(ns, key, entry)? {
-                        stale.push((ns.clone(), key.clone()));
+                    if !self.is_live(ns, key, entry)? {
+                        let names = match watched {
+                            true => Some(self.entry_names(ns, key, entry)?),
+                            false => None,
+                        };
+                        stale.push((ns.clone(), key.clone(), names));
                     }
                 }
             }
-            for (ns, key) in &stale {
-                if let Some(bucket) = g.get_mut(ns) {
-                    bucket.shift_remove(key);
-                }
+            for (ns_id, key_id, names) in &stale {
+                let names = names.as_ref().map(|(ns, key)| (ns.as_str(), key.as_str()));
+                take_from(self, &mut g, (ns_id, key_id), names, EventKind::Expire);
             }
             stale.len()
         };
         if removed > 0 {
-            self.inner.after_write()?;
+            self.after_write()?;
         }
         Ok(removed)
     }
-}
 
-impl Inner {
     pub(crate) fn ensure_writable(&self) -> Result<()> {
         if self.read_only {
             Err(Error::ReadOnly)
@@ -468,19 +1164,44 @@ impl Inner {
         if self.read_only {
             return Err(Error::ReadOnly);
         }
-        self.persist()?;
-        self.dirty.store(false, Ordering::Release);
+        self.dirty.swap(false, Ordering::AcqRel);
+        self.write_out()
+    }
+
+    /// Persist, with the dirty flag already cleared: a write landing meanwhile sets it
+    /// again, for the next save to pick up. A failed save sets it back.
+    fn write_out(&self) -> Result<()> {
+        if let Err(e) = self.persist() {
+            self.dirty.store(true, Ordering::Release);
+            return Err(e);
+        }
         if let Ok(mut last) = self.last_save.lock() {
             *last = Instant::now();
         }
         Ok(())
     }
 
-    /// Apply the auto-save policy after a successful mutation.
+    /// Whether [`Inner::save`] has somewhere to write to.
+    pub(crate) fn can_save(&self) -> bool {
+        self.backend.is_some() && !self.read_only
+    }
+
+    /// Save if anything changed since the last save.
+    pub(crate) fn save_if_dirty(&self) -> Result<()> {
+        if self.read_only || !self.dirty.swap(false, Ordering::AcqRel) {
+            return Ok(());
+        }
+        self.write_out()
+    }
+
+    /// Tell watchers, then apply the auto-save policy, after a successful mutation.
     pub(crate) fn after_write(&self) -> Result<()> {
+        self.dispatch_events()?;
         self.dirty.store(true, Ordering::Release);
         match self.autosave {
             AutoSave::OnEveryWrite => self.save(),
+            // the timer thread saves; without one, writes check the time themselves
+            AutoSave::Periodic(_) if self.autosaver.get().is_some() => Ok(()),
             AutoSave::Periodic(d) => {
                 let elapsed = self
                     .last_save
@@ -493,6 +1214,12 @@ impl Inner {
                     Ok(())
                 }
             }
+            AutoSave::Debounced(_) => {
+                if let Some(autosaver) = self.autosaver.get() {
+                    autosaver.wake();
+                }
+                Ok(())
+            }
             AutoSave::Manual | AutoSave::OnDrop => Ok(()),
         }
     }
@@ -508,142 +1235,734 @@ impl Inner {
     /// Decrypt + deserialize an entry, honoring its authenticated expiry.
     pub(crate) fn read_value<V: DeserializeOwned>(
         &self,
-        ns: &str,
-        key: &str,
+        ns_id: &str,
+        key_id: &str,
         entry: &Entry,
     ) -> Result<Option<V>> {
-        match self.open_entry(ns, key, entry)? {
-            Some(bytes) => Ok(Some(decode(bytes)?)),
+        match self.open_entry(ns_id, key_id, entry)? {
+            Some(bytes) => Ok(Some(self.codec(ns_id).decode(bytes)?)),
             None => Ok(None),
         }
     }
 
+    /// [`Inner::read_value`] for raw values.
+    pub(crate) fn read_raw(
+        &self,
+        ns_id: &str,
+        key_id: &str,
+        entry: &Entry,
+    ) -> Result<Option<Vec<u8>>> {
+        self.open_entry(ns_id, key_id, entry)?
+            .map(decode_raw)
+            .transpose()
+    }
+
+    /// A self-contained copy of the store (no journal).
     fn serialize(&self) -> Result<Vec<u8>> {
         // lock order: storage, then crypto (matches rekey).
         let store = self.read_store()?;
         let crypto = self.crypto.read().map_err(|_| Error::Locked)?;
-        let file = StoreFileRef {
-            magic: MAGIC,
-            version: FORMAT_VERSION,
-            kdf: &crypto.kdf,
-            salt: &crypto.salt,
-            verifier: &crypto.verifier,
-            trees: &store,
+        let journal = self.journal.lock().map_err(|_| Error::Locked)?;
+        let generation = self.generation.load(Ordering::Acquire);
+        let integrity = self.integrity(&store, &crypto, &journal, generation)?;
+        serialize_file(&store, &crypto, 0, integrity.map(|(tag, _)| tag))
+    }
+
+    /// The integrity tag for `store` at `generation`, if this store keeps one, and the tally
+    /// behind it: the last flush's, with just the keys touched since folded in again.
+    fn integrity(
+        &self,
+        store: &Store,
+        crypto: &Crypto,
+        journal: &Journal,
+        generation: u64,
+    ) -> Result<Option<(Integrity, Tally)>> {
+        if !self.manifest {
+            return Ok(None);
+        }
+        let key = crypto.key.derive(MANIFEST_KEY_LABEL)?;
+        let tally = match journal.tally {
+            Some(mut tally) => {
+                for ((ns, k), flushed) in &journal.touched {
+                    if let Some(nonce) = flushed {
+                        tally.remove(&key, (ns, k), nonce);
+                    }
+                    if let Some(entry) = store.get(ns).and_then(|b| b.get(k)) {
+                        tally.add(&key, (ns, k), &entry.nonce);
+                    }
+                }
+                tally
+            }
+            None => Tally::of(&key, store),
         };
-        rmp_serde::to_vec(&file).map_err(|e| Error::Serialization(e.to_string()))
+        Ok(Some((tally.seal(&key, generation), tally)))
     }
 
     fn persist(&self) -> Result<()> {
-        let path = self.path.clone().ok_or(Error::NoPath)?;
+        let backend = self.backend.as_deref().ok_or(Error::NoPath)?;
         let _guard = self.commit_lock.lock().map_err(|_| Error::Locked)?;
-        let bytes = self.serialize()?;
-        atomic_write(&path, &bytes)
+        // only ever locked under `commit_lock`, so holding it across I/O blocks no one
+        let mut wal = self.wal.lock().map_err(|_| Error::Locked)?;
+
+        let rewrite = !self.journaled
+            || wal.log_id == 0
+            || self.journal.lock().map_err(|_| Error::Locked)?.rewrite;
+        if !rewrite {
+            self.append_journal(backend, &mut wal)?;
+            if !wal.needs_compaction() {
+                return Ok(());
+            }
+        }
+        self.rewrite(backend, &mut wal)
     }
 
-    /// Seal a value, bound to `(ns, key)`. Expiry is framed into the plaintext, so it's
-    /// encrypted and authenticated too.
-    pub(crate) fn seal(
+    /// Log the current state of every touched key as one record.
+    fn append_journal(&self, backend: &dyn Backend, wal: &mut Wal) -> Result<()> {
+        // Snapshot under the data locks, then append without them.
+        let generation = self.generation.load(Ordering::Acquire) + 1;
+        let (ops, touched, integrity, cipher) = {
+            let store = self.read_store()?;
+            let crypto = self.crypto.read().map_err(|_| Error::Locked)?;
+            let mut journal = self.journal.lock().map_err(|_| Error::Locked)?;
+            let integrity = self.integrity(&store, &crypto, &journal, generation)?;
+            let touched = std::mem::take(&mut journal.touched);
+            let ops: Vec<LogOp> = touched
+                .keys()
+                .map(|(ns, key)| match fetch(&store, ns, key) {
+                    Some(entry) => LogOp::Put {
+                        ns: ns.clone(),
+                        key: key.clone(),
+                        entry,
+                    },
+                    None => LogOp::Remove {
+                        ns: ns.clone(),
+                        key: key.clone(),
+                    },
+                })
+                .collect();
+            let cipher = crypto.cipher_for(crypto.epoch)?;
+            if !ops.is_empty() {
+                journal.tally = integrity.map(|(_, tally)| tally);
+            }
+            (ops, touched, integrity.map(|(tag, _)| tag), cipher)
+        };
+        if ops.is_empty() {
+            return Ok(());
+        }
+        if let Err(e) = wal::append(backend, wal, &cipher, ops, integrity) {
+            // keep the keys pending, with their flushed nonces, so the next save retries
+            // them; the tally is recomputed rather than unwound
+            if let Ok(mut journal) = self.journal.lock() {
+                journal.touched.extend(touched);
+                journal.tally = None;
+            }
+            return Err(e);
+        }
+        self.generation.store(generation, Ordering::Release);
+        Ok(())
+    }
+
+    /// Rewrite the whole file under a fresh log id, which orphans any old journal even if
+    /// deleting it afterwards fails.
+    fn rewrite(&self, backend: &dyn Backend, wal: &mut Wal) -> Result<()> {
+        let log_id = if self.journaled { nonzero_u64()? } else { 0 };
+        let generation = self.generation.load(Ordering::Acquire) + 1;
+        let bytes = {
+            let store = self.read_store()?;
+            let crypto = self.crypto.read().map_err(|_| Error::Locked)?;
+            let mut journal = self.journal.lock().map_err(|_| Error::Locked)?;
+            let integrity = self.integrity(&store, &crypto, &journal, generation)?;
+            let bytes = serialize_file(&store, &crypto, log_id, integrity.map(|(tag, _)| tag))?;
+            *journal = Journal {
+                tally: integrity.map(|(_, tally)| tally),
+                ..Default::default()
+            };
+            bytes
+        };
+        if let Err(e) = backend.store(&bytes) {
+            self.request_rewrite();
+            return Err(e);
+        }
+        self.generation.store(generation, Ordering::Release);
+        if wal.log_id != 0 || wal.len != 0 {
+            backend.remove_journal()?;
+        }
+        *wal = Wal {
+            log_id,
+            base_len: bytes.len() as u64,
+            ..Default::default()
+        };
+        Ok(())
+    }
+
+    /// Note a key written under the storage write lock, with the entry it held before, for
+    /// the next journal flush and integrity tally.
+    pub(crate) fn touch(&self, ns: &str, key: &str, old: Option<&Entry>) {
+        let tallied = self.manifest && self.backend.is_some();
+        if !self.journaled && !tallied {
+            return;
+        }
+        if let Ok(mut journal) = self.journal.lock() {
+            // only the first touch since a flush still holds what was flushed
+            journal
+                .touched
+                .entry((ns.to_string(), key.to_string()))
+                .or_insert_with(|| old.map(|e| e.nonce.clone()));
+        }
+    }
+
+    /// Subscribe to changes to `target` in namespace `ns`.
+    pub(crate) fn subscribe(
         &self,
         ns: &str,
-        key: &str,
+        target: Target,
+        values: bool,
+    ) -> Result<Receiver<Event>> {
+        let mut watchers = self.watchers.lock().map_err(|_| Error::Locked)?;
+        Ok(watchers.subscribe(ns, target, values))
+    }
+
+    /// Whether anything is subscribed at all.
+    pub(crate) fn watched(&self) -> bool {
+        self.watchers.lock().is_ok_and(|w| !w.is_empty())
+    }
+
+    /// Whether anything watches keys in namespace `ns`.
+    pub(crate) fn watching(&self, ns: &str) -> bool {
+        self.watchers.lock().is_ok_and(|w| w.watching(ns))
+    }
+
+    /// Record a change under the storage write lock, for the watchers of `(ns, key)`. The
+    /// plaintext is only kept if one of them asked for values.
+    pub(crate) fn notify(
+        &self,
+        ns_id: &str,
+        names: (&str, &str),
+        kind: EventKind,
+        value: Option<&[u8]>,
+    ) {
+        let Ok(mut watchers) = self.watchers.lock() else {
+            return;
+        };
+        if let Some(values) = watchers.interest(names.0, names.1) {
+            let value = value.filter(|_| values);
+            watchers.record(names, kind, value, self.codec(ns_id));
+        }
+    }
+
+    /// How many events are waiting to be sent; see [`Inner::drop_events_since`].
+    pub(crate) fn events_mark(&self) -> usize {
+        self.watchers.lock().map(|w| w.mark()).unwrap_or(0)
+    }
+
+    /// Forget the events recorded since `mark`, for writes that were rolled back.
+    pub(crate) fn drop_events_since(&self, mark: usize) {
+        if let Ok(mut watchers) = self.watchers.lock() {
+            watchers.rollback(mark);
+        }
+    }
+
+    /// Send the events writes have recorded. Waiting on the read lock means none go out
+    /// while a write (or transaction) still holds the store.
+    fn dispatch_events(&self) -> Result<()> {
+        let quiet = self.read_store()?;
+        let mut watchers = self.watchers.lock().map_err(|_| Error::Locked)?;
+        drop(quiet);
+        watchers.dispatch();
+        Ok(())
+    }
+
+    /// [`MicroKV::rotate_step`] without the write's follow-up: returns whether anything
+    /// changed, and how many entries are left to migrate.
+    fn reseal(&self, n: usize) -> Result<(bool, usize)> {
+        let mut sg = self.write_store()?;
+        let mut cg = self.crypto.write().map_err(|_| Error::Locked)?;
+        let cipher = cg.cipher_for(cg.epoch)?;
+
+        let (mut migrated, mut remaining) = (0, 0);
+        for (ns, bucket) in sg.iter_mut() {
+            for (key, entry) in bucket.iter_mut() {
+                if entry.epoch == cg.epoch {
+                    continue;
+                }
+                if migrated == n {
+                    remaining += 1;
+                    continue;
+                }
+                let aad = value_aad(ns, key);
+                let old = cg.cipher_for(entry.epoch)?;
+                let mut pt = aead_decrypt(&old, &aad, &entry.nonce, &entry.data)?;
+                let sealed = aead_encrypt(&cipher, &aad, &pt);
+                pt.zeroize();
+                self.touch(ns, key, Some(&*entry));
+                *entry = Entry {
+                    epoch: cg.epoch,
+                    ..Entry::new(sealed?)
+                };
+                migrated += 1;
+            }
+        }
+
+        let retired = remaining == 0 && !cg.retired.is_empty();
+        if retired {
+            cg.retired.clear();
+            self.request_rewrite();
+        }
+        Ok((migrated > 0 || retired, remaining))
+    }
+
+    /// Change the key slots under the crypto lock, then re-mint the verifier over the new
+    /// header. Slots live in the header, so the next save rewrites the file.
+    fn update_slots<R>(&self, f: impl FnOnce(&mut Crypto) -> Result<R>) -> Result<R> {
+        self.ensure_writable()?;
+        let result = {
+            let mut c = self.crypto.write().map_err(|_| Error::Locked)?;
+            let result = f(&mut c)?;
+            c.remint()?;
+            result
+        };
+        self.request_rewrite();
+        self.after_write()?;
+        Ok(result)
+    }
+
+    /// Force the next save to rewrite the whole file.
+    fn request_rewrite(&self) {
+        if let Ok(mut journal) = self.journal.lock() {
+            journal.rewrite = true;
+            journal.tally = None;
+        }
+    }
+
+    pub(crate) fn encrypted_names(&self) -> bool {
+        self.encrypted_names
+    }
+
+    /// The map keys `(ns, key)` live under: the names themselves, or their blind indexes
+    /// when the store encrypts names.
+    pub(crate) fn locate(&self, ns: &str, key: &str) -> Result<(String, String)> {
+        if !self.encrypted_names {
+            return Ok((ns.to_string(), key.to_string()));
+        }
+        let crypto = self.crypto.read().map_err(|_| Error::Locked)?;
+        let names = crypto.names.as_ref().ok_or(Error::Crypto)?;
+        Ok((
+            blind_index(&names.key, b"ns", &[ns]),
+            blind_index(&names.key, b"key", &[ns, key]),
+        ))
+    }
+
+    /// The value cipher entries are sealed with.
+    pub(crate) fn cipher(&self) -> Result<Cipher> {
+        Ok(self.crypto.read().map_err(|_| Error::Locked)?.cipher)
+    }
+
+    /// The padding policy, keyed by namespace name (rather than blind index) for the
+    /// `namespaces` given.
+    pub(crate) fn padding_by_name<'a>(
+        &self,
+        namespaces: impl IntoIterator<Item = &'a str>,
+    ) -> Result<Padding> {
+        match &self.padding {
+            Padding::Namespaces(sizes) if self.encrypted_names => {
+                let mut by_name = std::collections::BTreeMap::new();
+                for ns in namespaces {
+                    if let Some(&n) = sizes.get(&self.ns_id(ns)?) {
+                        by_name.insert(ns.to_string(), n);
+                    }
+                }
+                Ok(Padding::Namespaces(by_name))
+            }
+            padding => Ok(padding.clone()),
+        }
+    }
+
+    /// The codec for values in namespace map key `ns_id`.
+    pub(crate) fn codec(&self, ns_id: &str) -> Codec {
+        self.codecs.get(ns_id).copied().unwrap_or(self.codec)
+    }
+
+    /// [`Inner::codec`], by namespace name.
+    pub(crate) fn codec_by_name(&self, ns: &str) -> Result<Codec> {
+        if self.codecs.is_empty() {
+            return Ok(self.codec);
+        }
+        Ok(self.codec(&self.ns_id(ns)?))
+    }
+
+    /// The codecs, with per-namespace overrides keyed by name (rather than blind index)
+    /// for the `namespaces` given.
+    pub(crate) fn codecs_by_name<'a>(
+        &self,
+        namespaces: impl IntoIterator<Item = &'a str>,
+    ) -> Result<(Codec, BTreeMap<String, Codec>)> {
+        if !self.encrypted_names {
+            return Ok((self.codec, self.codecs.clone()));
+        }
+        let mut by_name = BTreeMap::new();
+        for ns in namespaces {
+            if let Some(&codec) = self.codecs.get(&self.ns_id(ns)?) {
+                by_name.insert(ns.to_string(), codec);
+            }
+        }
+        Ok((self.codec, by_name))
+    }
+
+    /// Just the namespace half of [`Inner::locate`].
+    pub(crate) fn ns_id(&self, ns: &str) -> Result<String> {
+        if !self.encrypted_names {
+            return Ok(ns.to_string());
+        }
+        let crypto = self.crypto.read().map_err(|_| Error::Locked)?;
+        let names = crypto.names.as_ref().ok_or(Error::Crypto)?;
+        Ok(blind_index(&names.key, b"ns", &[ns]))
+    }
+
+    /// Seal a value, bound to where it's stored (`ids`, from [`Inner::locate`]). Expiry
+    /// (unix seconds) is framed into the plaintext, so it's encrypted and authenticated
+    /// too; so are `names`, when the map keys are blind indexes.
+    pub(crate) fn seal(
+        &self,
+        ids: (&str, &str),
+        names: (&str, &str),
         value: &[u8],
-        ttl: Option<Duration>,
+        expires_at: Option<u64>,
     ) -> Result<Entry> {
         let crypto = self.crypto.read().map_err(|_| Error::Locked)?;
-        let expires_at = ttl.map(|d| now_secs().saturating_add(d.as_secs()));
-        let mut framed = frame(expires_at, value);
-        let aad = value_aad(ns, key);
-        let (nonce, data) = aead_encrypt(&crypto.key.cipher(), &aad, &framed)?;
+        let names = self.encrypted_names.then_some(names);
+        let mut framed = frame(expires_at, names, value, |len| {
+            self.padding.padded_len(ids.0, len)
+        });
+        let aad = value_aad(ids.0, ids.1);
+        let sealed = crypto
+            .cipher_for(crypto.epoch)
+            .and_then(|cipher| aead_encrypt(&cipher, &aad, &framed));
         framed.zeroize();
-        Ok(Entry { nonce, data })
+        Ok(Entry {
+            epoch: crypto.epoch,
+            ..Entry::new(sealed?)
+        })
     }
 
-    /// Authenticate + decrypt, then apply expiry (`None` if expired). Decrypting before
-    /// checking expiry means a tampered expiry fails auth rather than passing silently.
-    pub(crate) fn open_entry(&self, ns: &str, key: &str, entry: &Entry) -> Result<Option<Vec<u8>>> {
+    /// Authenticate + decrypt, ignoring expiry.
+    pub(crate) fn unseal(&self, ns_id: &str, key_id: &str, entry: &Entry) -> Result<Frame> {
         let crypto = self.crypto.read().map_err(|_| Error::Locked)?;
-        let aad = value_aad(ns, key);
-        let mut framed = aead_decrypt(&crypto.key.cipher(), &aad, &entry.nonce, &entry.data)?;
-        let result = unframe(&framed).map(|(expires_at, value)| {
-            if expires_at.is_some_and(|exp| now_secs() >= exp) {
-                None
-            } else {
-                Some(value)
-            }
-        });
+        let aad = value_aad(ns_id, key_id);
+        let cipher = crypto.cipher_for(entry.epoch)?;
+        let mut framed = aead_decrypt(&cipher, &aad, &entry.nonce, &entry.data)?;
+        let result = unframe(&framed);
         framed.zeroize();
         result
     }
 
+    /// [`Inner::unseal`], then apply expiry (`None` if expired). Decrypting before checking
+    /// expiry means a tampered expiry fails auth rather than passing silently.
+    pub(crate) fn open_frame(
+        &self,
+        ns_id: &str,
+        key_id: &str,
+        entry: &Entry,
+    ) -> Result<Option<Frame>> {
+        let frame = self.unseal(ns_id, key_id, entry)?;
+        if frame.expires_at.is_some_and(|exp| now_secs() >= exp) {
+            Ok(None)
+        } else {
+            Ok(Some(frame))
+        }
+    }
+
+    /// The live value of an entry, if any.
+    pub(crate) fn open_entry(
+        &self,
+        ns_id: &str,
+        key_id: &str,
+        entry: &Entry,
+    ) -> Result<Option<Vec<u8>>> {
+        Ok(self
+            .open_frame(ns_id, key_id, entry)?
+            .map(|mut frame| std::mem::take(&mut frame.value)))
+    }
+
     /// Present and not expired, without exposing the value.
-    pub(crate) fn is_live(&self, ns: &str, key: &str, entry: &Entry) -> Result<bool> {
-        match self.open_entry(ns, key, entry)? {
-            Some(mut value) => {
-                value.zeroize();
-                Ok(true)
+    pub(crate) fn is_live(&self, ns_id: &str, key_id: &str, entry: &Entry) -> Result<bool> {
+        Ok(self.open_frame(ns_id, key_id, entry)?.is_some())
+    }
+
+    /// Every live entry in the namespaces `keep` accepts (by map key), decrypted and paired
+    /// with its namespace and key names.
+    pub(crate) fn open_all(
+        &self,
+        store: &Store,
+        keep: impl Fn(&str) -> bool,
+    ) -> Result<Vec<(String, String, Frame)>> {
+        let mut out = Vec::new();
+        for (ns_id, bucket) in store.iter().filter(|(ns_id, _)| keep(ns_id)) {
+            for (key_id, entry) in bucket.iter() {
+                let Some(mut frame) = self.open_frame(ns_id, key_id, entry)? else {
+                    continue;
+                };
+                let (ns, key) = match (frame.names.take(), self.encrypted_names) {
+                    (_, false) => (ns_id.clone(), key_id.clone()),
+                    (Some(names), true) => names,
+                    (None, true) => return Err(Error::Crypto),
+                };
+                out.push((ns, key, frame));
             }
-            None => Ok(false),
         }
+        Ok(out)
     }
-}
 
-/// Frame a value with its optional expiry for sealing: `[flag][expiry_le?] ++ value`.
-fn frame(expires_at: Option<u64>, value: &[u8]) -> Vec<u8> {
-    let mut out = Vec::with_capacity(9 + value.len());
-    match expires_at {
-        Some(ts) => {
-            out.push(1);
-            out.extend_from_slice(&ts.to_le_bytes());
+    /// The `(namespace, key)` names of an entry, decrypting it if names are encrypted.
+    pub(crate) fn entry_names(
+        &self,
+        ns_id: &str,
+        key_id: &str,
+        entry: &Entry,
+    ) -> Result<(String, String)> {
+        if !self.encrypted_names {
+            return Ok((ns_id.to_string(), key_id.to_string()));
+        }
+        self.unseal(ns_id, key_id, entry)?
+            .names
+            .take()
+            .ok_or(Error::Crypto)
+    }
+
+    /// The key name of a decrypted entry stored under `key_id`.
+    pub(crate) fn key_name(&self, key_id: &str, frame: &Frame) -> Result<String> {
+        match (&frame.names, self.encrypted_names) {
+            (_, false) => Ok(key_id.to_string()),
+            (Some((_, key)), true) => Ok(key.clone()),
+            (None, true) => Err(Error::Crypto),
         }
-        None => out.push(0),
+    }
+}
+
+/// A decrypted entry. The value is wiped on drop.
+pub(crate) struct Frame {
+    pub(crate) expires_at: Option<u64>,
+    /// `(namespace, key)`, in stores with encrypted names.
+    pub(crate) names: Option<(String, String)>,
+    pub(crate) value: Vec<u8>,
+}
+
+impl Drop for Frame {
+    fn drop(&mut self) {
+        self.value.zeroize();
+    }
+}
+
+const FRAME_EXPIRY: u8 = 0b001;
+const FRAME_NAMES: u8 = 0b010;
+const FRAME_PADDED: u8 = 0b100;
+
+/// Frame a value with its optional expiry and names for sealing:
+/// `[flags][expiry_le?][ns_len_le ns key_len_le key]?[pad_len_le?] ++ value ++ zeros`.
+/// `pad_to` maps the unpadded length to the padded one, or `None` to leave it be. Without
+/// names or padding this is the v3 layout, whose flag byte was just `0` or `1`.
+fn frame(
+    expires_at: Option<u64>,
+    names: Option<(&str, &str)>,
+    value: &[u8],
+    pad_to: impl FnOnce(usize) -> Option<usize>,
+) -> Vec<u8> {
+    let names_len = names.map(|(ns, key)| 8 + ns.len() + key.len()).unwrap_or(0);
+    let len = 1 + expires_at.map_or(0, |_| 8) + names_len + 4 + value.len();
+    // padded frames always carry the pad length, so every one in a bucket is the same size
+    let padded = pad_to(len);
+    let mut out = Vec::with_capacity(padded.unwrap_or(len));
+    let mut flags = 0;
+    if expires_at.is_some() {
+        flags |= FRAME_EXPIRY;
+    }
+    if names.is_some() {
+        flags |= FRAME_NAMES;
+    }
+    if padded.is_some() {
+        flags |= FRAME_PADDED;
+    }
+    out.push(flags);
+    if let Some(ts) = expires_at {
+        out.extend_from_slice(&ts.to_le_bytes());
+    }
+    if let Some((ns, key)) = names {
+        out.extend_from_slice(&value_aad(ns, key));
+    }
+    let pad = padded.map(|p| p.saturating_sub(len));
+    if let Some(pad) = pad {
+        out.extend_from_slice(&(pad as u32).to_le_bytes());
     }
     out.extend_from_slice(value);
+    out.resize(out.len() + pad.unwrap_or(0), 0);
     out
 }
 
 /// Inverse of [`frame`]; a malformed frame counts as a crypto failure.
-fn unframe(buf: &[u8]) -> Result<(Option<u64>, Vec<u8>)> {
-    match buf.first() {
-        Some(0) => Ok((None, buf[1..].to_vec())),
-        Some(1) if buf.len() >= 9 => {
-            let mut ts = [0u8; 8];
-            ts.copy_from_slice(&buf[1..9]);
-            Ok((Some(u64::from_le_bytes(ts)), buf[9..].to_vec()))
-        }
-        _ => Err(Error::Crypto),
+fn unframe(buf: &[u8]) -> Result<Frame> {
+    let (&flags, mut rest) = buf.split_first().ok_or(Error::Crypto)?;
+    if flags & !(FRAME_EXPIRY | FRAME_NAMES | FRAME_PADDED) != 0 {
+        return Err(Error::Crypto);
+    }
+    let expires_at = if flags & FRAME_EXPIRY != 0 {
+        let ts: [u8; 8] = take(&mut rest, 8)?.try_into().map_err(|_| Error::Crypto)?;
+        Some(u64::from_le_bytes(ts))
+    } else {
+        None
+    };
+    let names = if flags & FRAME_NAMES != 0 {
+        let ns = take_str(&mut rest)?;
+        let key = take_str(&mut rest)?;
+        Some((ns, key))
+    } else {
+        None
+    };
+    if flags & FRAME_PADDED != 0 {
+        let pad: [u8; 4] = take(&mut rest, 4)?.try_into().map_err(|_| Error::Crypto)?;
+        let len = rest
+            .len()
+            .checked_sub(u32::from_le_bytes(pad) as usize)
+            .ok_or(Error::Crypto)?;
+        rest = &rest[..len];
     }
+    Ok(Frame {
+        expires_at,
+        names,
+        value: rest.to_vec(),
+    })
+}
+
+fn take<'a>(buf: &mut &'a [u8], n: usize) -> Result<&'a [u8]> {
+    if buf.len() < n {
+        return Err(Error::Crypto);
+    }
+    let (head, tail) = buf.split_at(n);
+    *buf = tail;
+    Ok(head)
+}
+
+fn take_str(buf: &mut &[u8]) -> Result<String> {
+    let len: [u8; 4] = take(buf, 4)?.try_into().map_err(|_| Error::Crypto)?;
+    let bytes = take(buf, u32::from_le_bytes(len) as usize)?;
+    String::from_utf8(bytes.to_vec()).map_err(|_| Error::Crypto)
 }
 
 impl Drop for Inner {
     fn drop(&mut self) {
-        let should_flush = matches!(self.autosave, AutoSave::OnDrop | AutoSave::Periodic(_));
+        let should_flush = matches!(
+            self.autosave,
+            AutoSave::OnDrop | AutoSave::Periodic(_) | AutoSave::Debounced(_)
+        );
         if should_flush
-            && self.path.is_some()
+            && self.backend.is_some()
             && !self.read_only
             && self.dirty.load(Ordering::Acquire)
         {
             let _ = self.persist();
         }
-        // _file_lock is released as its File drops.
+        // _lock is released as it drops.
     }
 }
 
 /* ============================ Shared store operations ============================ */
 
-fn to_path(p: impl AsRef<Path>) -> PathBuf {
-    p.as_ref().to_path_buf()
+fn on_disk(p: impl AsRef<Path>) -> Box<dyn Backend> {
+    Box::new(FileBackend::new(p))
+}
+
+fn serialize_file(
+    store: &Store,
+    crypto: &Crypto,
+    log_id: u64,
+    integrity: Option<Integrity>,
+) -> Result<Vec<u8>> {
+    let file = StoreFileRef {
+        magic: MAGIC,
+        version: crypto.version(),
+        kdf: &crypto.kdf,
+        salt: &crypto.salt,
+        verifier: &crypto.verifier,
+        trees: store,
+        log_id,
+        features: &crypto.features,
+        name_key: crypto.names.as_ref().map(|n| &n.sealed),
+        integrity,
+        slots: &crypto.slots,
+        data_key: crypto.data_key.as_ref(),
+        retired_keys: crypto.retired.iter().map(|r| &r.sealed).collect(),
+    };
+    rmp_serde::to_vec(&file).map_err(|e| Error::Serialization(e.to_string()))
+}
+
+/// The master key for `cred`: unwrapped from the first key slot it opens, or derived
+/// directly for stores without slots. Also returns the slot used.
+fn unlock(cred: &Credential, sf: &StoreFile) -> Result<(SecretKey, Option<u32>)> {
+    if sf.slots.is_empty() {
+        let mut key_bytes = credential_key(cred, &sf.kdf, &sf.salt)?;
+        let secret = SecretKey::new(key_bytes)?;
+        key_bytes.zeroize();
+        return Ok((secret, None));
+    }
+    for slot in &sf.slots {
+        match slot.open(cred) {
+            Ok(secret) => return Ok((secret, Some(slot.id))),
+            Err(Error::WrongPassword) => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Err(Error::WrongPassword)
+}
+
+/// Seal the verifier under `key`, binding `header` into its associated data.
+pub(crate) fn seal_verifier(key: &SecretKey, header: &[u8]) -> Result<Entry> {
+    Ok(Entry::new(aead_encrypt(
+        &key.cipher(),
+        header,
+        VERIFIER_PLAINTEXT,
+    )?))
 }
 
-pub(crate) fn fetch(store: &Store, ns: &str, key: &str) -> Option<Entry> {
-    store.get(ns).and_then(|b| b.get(key)).cloned()
+/// A random id, never `0` (which means "no journal").
+fn nonzero_u64() -> Result<u64> {
+    loop {
+        let v = crate::crypto::rand_u64()?;
+        if v != 0 {
+            return Ok(v);
+        }
+    }
 }
 
-/// Returns whether the key existed; preserves the order of remaining keys.
-pub(crate) fn remove_from(store: &mut Store, ns: &str, key: &str) -> bool {
-    store
-        .get_mut(ns)
-        .map(|b| b.shift_remove(key).is_some())
-        .unwrap_or(false)
+/// Look up by map keys (see [`Inner::locate`]).
+pub(crate) fn fetch(store: &Store, ns_id: &str, key_id: &str) -> Option<Entry> {
+    store.get(ns_id).and_then(|b| b.get(key_id)).cloned()
+}
+
+/// Remove by map keys, with the names watchers know it by. Returns whether the key existed.
+pub(crate) fn remove_from(
+    inner: &Inner,
+    store: &mut Store,
+    ids: (&str, &str),
+    names: (&str, &str),
+) -> bool {
+    take_from(inner, store, ids, Some(names), EventKind::Remove)
+}
+
+/// [`remove_from`], reporting the change as `kind`, or to nobody without `names`.
+fn take_from(
+    inner: &Inner,
+    store: &mut Store,
+    (ns_id, key_id): (&str, &str),
+    names: Option<(&str, &str)>,
+    kind: EventKind,
+) -> bool {
+    let old = store.get_mut(ns_id).and_then(|b| b.remove(key_id));
+    let existed = old.is_some();
+    if existed {
+        inner.touch(ns_id, key_id, old.as_ref());
+        if let Some(names) = names {
+            inner.notify(ns_id, names, kind, None);
+        }
+    }
+    existed
 }
 
 /// Encode + seal + insert under `(ns, key)`.
@@ -655,12 +1974,59 @@ pub(crate) fn seal_into<V: Serialize>(
     value: &V,
     ttl: Option<Duration>,
 ) -> Result<()> {
-    let mut plaintext = encode(value)?;
-    let entry = inner.seal(ns, key, &plaintext, ttl)?;
+    let mut plaintext = inner.codec_by_name(ns)?.encode(value)?;
+    let result = seal_encoded_into(inner, store, ns, key, &plaintext, expiry(ttl));
     plaintext.zeroize();
-    store
-        .entry(ns.to_string())
-        .or_default()
-        .insert(key.to_string(), entry);
+    result
+}
+
+/// When an entry written now with `ttl` expires, in unix seconds.
+pub(crate) fn expiry(ttl: Option<Duration>) -> Option<u64> {
+    ttl.map(|d| now_secs().saturating_add(d.as_secs()))
+}
+
+/// [`seal_into`] for an already-encoded value, expiring at `expires_at` (unix seconds).
+pub(crate) fn seal_encoded_into(
+    inner: &Inner,
+    store: &mut Store,
+    ns: &str,
+    key: &str,
+    plaintext: &[u8],
+    expires_at: Option<u64>,
+) -> Result<()> {
+    let ns_id = store_sealed(inner, store, (ns, key), plaintext, expires_at)?;
+    inner.notify(&ns_id, (ns, key), EventKind::Put, Some(plaintext));
+    Ok(())
+}
+
+/// Re-seal a key's current (encoded) value to expire at `expires_at` instead; watchers see
+/// [`EventKind::Ttl`].
+pub(crate) fn retime_into(
+    inner: &Inner,
+    store: &mut Store,
+    (ns, key): (&str, &str),
+    plaintext: &[u8],
+    expires_at: Option<u64>,
+) -> Result<()> {
+    let ns_id = store_sealed(inner, store, (ns, key), plaintext, expires_at)?;
+    inner.notify(&ns_id, (ns, key), EventKind::Ttl, None);
     Ok(())
 }
+
+/// Seal and store an entry, returning its namespace id.
+fn store_sealed(
+    inner: &Inner,
+    store: &mut Store,
+    (ns, key): (&str, &str),
+    plaintext: &[u8],
+    expires_at: Option<u64>,
+) -> Result<String> {
+    let (ns_id, key_id) = inner.locate(ns, key)?;
+    let sealed = inner.seal((&ns_id, &key_id), (ns, key), plaintext, expires_at)?;
+    let old = store
+        .entry(ns_id.clone())
+        .or_default()
+        .insert(key_id.clone(), sealed);
+    inner.touch(&ns_id, &key_id, old.as_ref());
+    Ok(ns_id)
+}
@@ -1,18 +1,23 @@
 //! [`Tree`]: the per-namespace key-value API. Every read/write op lives here; `MicroKV`
 //! exposes the default namespace's tree directly via `Deref`.
 
-use std::ops::ControlFlow;
+use std::ops::{ControlFlow, RangeBounds};
+use std::sync::mpsc::Receiver;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use serde::de::DeserializeOwned;
 use serde::Serialize;
+use zeroize::Zeroize;
 
-use crate::codec::encode;
+use crate::codec::encode_raw;
 use crate::error::{Error, Result};
-use crate::format::{Entry, Store};
+use crate::format::{now_secs, Store};
+use crate::iter::{decoded, edge, Cursor, Iter, Keys};
+use crate::key::key_name;
 use crate::secret::Secret;
-use crate::store::{fetch, remove_from, seal_into, Inner};
+use crate::store::{expiry, fetch, remove_from, retime_into, seal_encoded_into, seal_into, Inner};
+use crate::watch::{Event, EventKind, Target};
 
 /// A handle to a single namespace within a [`MicroKV`](crate::MicroKV) store.
 #[derive(Clone)]
@@ -31,70 +36,207 @@ impl Tree {
         &self.name
     }
 
-    pub fn get<V: DeserializeOwned>(&self, key: &str) -> Result<Option<V>> {
+    pub fn get<V: DeserializeOwned>(&self, key: impl AsRef<[u8]>) -> Result<Option<V>> {
+        let key = key_name(key.as_ref());
+        let (ns_id, key_id) = self.inner.locate(&self.name, &key)?;
         let entry = {
             let g = self.inner.read_store()?;
-            fetch(&g, &self.name, key)
+            fetch(&g, &ns_id, &key_id)
         };
         match entry {
-            Some(e) => self.inner.read_value(&self.name, key, &e),
+            Some(e) => self.inner.read_value(&ns_id, &key_id, &e),
             None => Ok(None),
         }
     }
 
-    pub fn require<V: DeserializeOwned>(&self, key: &str) -> Result<V> {
+    pub fn require<V: DeserializeOwned>(&self, key: impl AsRef<[u8]>) -> Result<V> {
         self.get(key)?.ok_or(Error::KeyNotFound)
     }
 
     /// [`Tree::get`], wrapped in a non-logging [`Secret`].
-    pub fn get_secret<V: DeserializeOwned>(&self, key: &str) -> Result<Option<Secret<V>>> {
+    pub fn get_secret<V: DeserializeOwned>(
+        &self,
+        key: impl AsRef<[u8]>,
+    ) -> Result<Option<Secret<V>>> {
         Ok(self.get::<V>(key)?.map(Secret::new))
     }
 
-    pub fn put<V: Serialize>(&self, key: &str, value: &V) -> Result<()> {
-        self.put_inner(key, value, None)
+    pub fn put<V: Serialize>(&self, key: impl AsRef<[u8]>, value: &V) -> Result<()> {
+        self.put_inner(&key_name(key.as_ref()), value, None)
     }
 
-    pub fn put_with_ttl<V: Serialize>(&self, key: &str, value: &V, ttl: Duration) -> Result<()> {
-        self.put_inner(key, value, Some(ttl))
+    pub fn put_with_ttl<V: Serialize>(
+        &self,
+        key: impl AsRef<[u8]>,
+        value: &V,
+        ttl: Duration,
+    ) -> Result<()> {
+        self.put_inner(&key_name(key.as_ref()), value, Some(ttl))
     }
 
     fn put_inner<V: Serialize>(&self, key: &str, value: &V, ttl: Option<Duration>) -> Result<()> {
+        let codec = self.inner.codec_by_name(&self.name)?;
+        self.put_encoded(key, codec.encode(value)?, ttl)
+    }
+
+    /// How long a live key has left, to the second; `None` if it doesn't expire. Fails with
+    /// [`Error::KeyNotFound`] if the key is missing or has expired.
+    pub fn ttl(&self, key: impl AsRef<[u8]>) -> Result<Option<Duration>> {
+        let key = key_name(key.as_ref());
+        let (ns_id, key_id) = self.inner.locate(&self.name, &key)?;
+        let entry = {
+            let g = self.inner.read_store()?;
+            fetch(&g, &ns_id, &key_id)
+        };
+        let frame = match entry {
+            Some(e) => self.inner.open_frame(&ns_id, &key_id, &e)?,
+            None => None,
+        };
+        let expires_at = frame.ok_or(Error::KeyNotFound)?.expires_at;
+        Ok(expires_at.map(|at| Duration::from_secs(at.saturating_sub(now_secs()))))
+    }
+
+    /// Make a live key expire `ttl` from now, keeping its value. Returns whether the key
+    /// was there to change. Watchers see an [`EventKind::Ttl`], without the value.
+    pub fn expire(&self, key: impl AsRef<[u8]>, ttl: Duration) -> Result<bool> {
+        let at = expiry(Some(ttl));
+        self.reframe(&key_name(key.as_ref()), |_| at)
+    }
+
+    /// [`Tree::expire`] at a point in time, to the second.
+    pub fn expire_at(&self, key: impl AsRef<[u8]>, at: SystemTime) -> Result<bool> {
+        let at = at
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        self.reframe(&key_name(key.as_ref()), |_| Some(at))
+    }
+
+    /// Stop a live key from expiring. Returns whether the key was there to change.
+    pub fn persist(&self, key: impl AsRef<[u8]>) -> Result<bool> {
+        self.reframe(&key_name(key.as_ref()), |_| None)
+    }
+
+    /// Push a live key's expiry out to `ttl` from now, if it would expire sooner: a
+    /// sliding deadline, e.g. for sessions. Keys with longer left, and keys that don't
+    /// expire, are left alone (see [`Tree::expire`] to give those a deadline). Returns
+    /// whether the key was there.
+    pub fn touch(&self, key: impl AsRef<[u8]>, ttl: Duration) -> Result<bool> {
+        let until = now_secs().saturating_add(ttl.as_secs());
+        self.reframe(&key_name(key.as_ref()), |at| at.map(|at| at.max(until)))
+    }
+
+    /// Re-seal a live entry's value as it is, with its expiry (unix seconds) run through
+    /// `retime`. Entries whose expiry doesn't change aren't rewritten.
+    fn reframe(&self, key: &str, retime: impl FnOnce(Option<u64>) -> Option<u64>) -> Result<bool> {
         self.inner.ensure_writable()?;
-        {
+        let (live, changed) = {
+            let (ns_id, key_id) = self.inner.locate(&self.name, key)?;
             let mut g = self.inner.write_store()?;
-            seal_into(&self.inner, &mut g, &self.name, key, value, ttl)?;
+            let frame = match fetch(&g, &ns_id, &key_id) {
+                Some(e) => self.inner.open_frame(&ns_id, &key_id, &e)?,
+                None => None,
+            };
+            match frame {
+                Some(frame) => {
+                    let expires_at = retime(frame.expires_at);
+                    let changed = expires_at != frame.expires_at;
+                    if changed {
+                        retime_into(
+                            &self.inner,
+                            &mut g,
+                            (&self.name, key),
+                            &frame.value,
+                            expires_at,
+                        )?;
+                    }
+                    (true, changed)
+                }
+                None => (false, false),
+            }
+        };
+        if changed {
+            self.inner.after_write()?;
         }
+        Ok(live)
+    }
+
+    /// A value written with [`Tree::put_raw`], as its bytes.
+    pub fn get_raw(&self, key: impl AsRef<[u8]>) -> Result<Option<Vec<u8>>> {
+        let key = key_name(key.as_ref());
+        let (ns_id, key_id) = self.inner.locate(&self.name, &key)?;
+        let entry = {
+            let g = self.inner.read_store()?;
+            fetch(&g, &ns_id, &key_id)
+        };
+        match entry {
+            Some(e) => self.inner.read_raw(&ns_id, &key_id, &e),
+            None => Ok(None),
+        }
+    }
+
+    /// Seal bytes as they are, skipping serde. Read them back with [`Tree::get_raw`] (or as
+    /// a msgpack bin, e.g. `serde_bytes::ByteBuf`, with [`Tree::get`]).
+    pub fn put_raw(&self, key: impl AsRef<[u8]>, value: &[u8]) -> Result<()> {
+        self.put_encoded(&key_name(key.as_ref()), encode_raw(value)?, None)
+    }
+
+    pub fn put_raw_with_ttl(
+        &self,
+        key: impl AsRef<[u8]>,
+        value: &[u8],
+        ttl: Duration,
+    ) -> Result<()> {
+        self.put_encoded(&key_name(key.as_ref()), encode_raw(value)?, Some(ttl))
+    }
+
+    fn put_encoded(&self, key: &str, mut plaintext: Vec<u8>, ttl: Option<Duration>) -> Result<()> {
+        let result = self.inner.ensure_writable().and_then(|()| {
+            let mut g = self.inner.write_store()?;
+            seal_encoded_into(
+                &self.inner,
+                &mut g,
+                &self.name,
+                key,
+                &plaintext,
+                expiry(ttl),
+            )
+        });
+        plaintext.zeroize();
+        result?;
         self.inner.after_write()
     }
 
-    pub fn remove(&self, key: &str) -> Result<bool> {
+    pub fn remove(&self, key: impl AsRef<[u8]>) -> Result<bool> {
+        let key = key_name(key.as_ref());
         self.inner.ensure_writable()?;
+        let (ns_id, key_id) = self.inner.locate(&self.name, &key)?;
         let existed = {
             let mut g = self.inner.write_store()?;
-            remove_from(&mut g, &self.name, key)
+            remove_from(&self.inner, &mut g, (&ns_id, &key_id), (&self.name, &key))
         };
         self.inner.after_write()?;
         Ok(existed)
     }
 
-    pub fn contains(&self, key: &str) -> Result<bool> {
+    pub fn contains(&self, key: impl AsRef<[u8]>) -> Result<bool> {
+        let key = key_name(key.as_ref());
+        let (ns_id, key_id) = self.inner.locate(&self.name, &key)?;
         let entry = {
             let g = self.inner.read_store()?;
-            fetch(&g, &self.name, key)
+            fetch(&g, &ns_id, &key_id)
         };
         match entry {
-            Some(e) => self.inner.is_live(&self.name, key, &e),
+            Some(e) => self.inner.is_live(&ns_id, &key_id, &e),
             None => Ok(false),
         }
     }
 
     pub fn len(&self) -> Result<usize> {
         let mut n = 0;
-        for (k, e) in self.snapshot_entries(|_| true)? {
-            if self.inner.is_live(&self.name, &k, &e)? {
-                n += 1;
-            }
+        for key in self.iter_keys()? {
+            key?;
+            n += 1;
         }
         Ok(n)
     }
@@ -104,38 +246,42 @@ impl Tree {
     }
 
     /// Atomic read-modify-write under one lock. Returning `None` removes the key.
-    pub fn update<V, F>(&self, key: &str, f: F) -> Result<()>
+    pub fn update<V, F>(&self, key: impl AsRef<[u8]>, f: F) -> Result<()>
     where
         V: Serialize + DeserializeOwned,
         F: FnOnce(Option<V>) -> Option<V>,
     {
+        let key = key_name(key.as_ref());
         self.inner.ensure_writable()?;
         {
+            let (ns_id, key_id) = self.inner.locate(&self.name, &key)?;
             let mut g = self.inner.write_store()?;
-            let current = self.load::<V>(&g, key)?;
+            let current = self.load::<V>(&g, &ns_id, &key_id)?;
             match f(current) {
-                Some(v) => seal_into(&self.inner, &mut g, &self.name, key, &v, None)?,
+                Some(v) => seal_into(&self.inner, &mut g, &self.name, &key, &v, None)?,
                 None => {
-                    remove_from(&mut g, &self.name, key);
+                    remove_from(&self.inner, &mut g, (&ns_id, &key_id), (&self.name, &key));
                 }
             }
         }
         self.inner.after_write()
     }
 
-    pub fn get_or_insert_with<V, F>(&self, key: &str, f: F) -> Result<V>
+    pub fn get_or_insert_with<V, F>(&self, key: impl AsRef<[u8]>, f: F) -> Result<V>
     where
         V: Serialize + DeserializeOwned,
         F: FnOnce() -> V,
     {
+        let key = key_name(key.as_ref());
         self.inner.ensure_writable()?;
         let (value, wrote) = {
+            let (ns_id, key_id) = self.inner.locate(&self.name, &key)?;
             let mut g = self.inner.write_store()?;
-            match self.load::<V>(&g, key)? {
+            match self.load::<V>(&g, &ns_id, &key_id)? {
                 Some(v) => (v, false),
                 None => {
                     let v = f();
-                    seal_into(&self.inner, &mut g, &self.name, key, &v, None)?;
+                    seal_into(&self.inner, &mut g, &self.name, &key, &v, None)?;
                     (v, true)
                 }
             }
@@ -149,27 +295,29 @@ impl Tree {
     /// Swap to `new` only if the current value equals `expected` (by serialized bytes).
     pub fn compare_and_swap<V: Serialize + DeserializeOwned>(
         &self,
-        key: &str,
+        key: impl AsRef<[u8]>,
         expected: Option<&V>,
         new: Option<&V>,
     ) -> Result<bool> {
+        let key = key_name(key.as_ref());
         self.inner.ensure_writable()?;
         let swapped = {
+            let (ns_id, key_id) = self.inner.locate(&self.name, &key)?;
             let mut g = self.inner.write_store()?;
-            let current_bytes = match fetch(&g, &self.name, key) {
-                Some(e) => self.inner.open_entry(&self.name, key, &e)?,
+            let current_bytes = match fetch(&g, &ns_id, &key_id) {
+                Some(e) => self.inner.open_entry(&ns_id, &key_id, &e)?,
                 None => None,
             };
             let expected_bytes = match expected {
-                Some(v) => Some(encode(v)?),
+                Some(v) => Some(self.inner.codec(&ns_id).encode(v)?),
                 None => None,
             };
 
             if current_bytes == expected_bytes {
                 match new {
-                    Some(v) => seal_into(&self.inner, &mut g, &self.name, key, v, None)?,
+                    Some(v) => seal_into(&self.inner, &mut g, &self.name, &key, v, None)?,
                     None => {
-                        remove_from(&mut g, &self.name, key);
+                        remove_from(&self.inner, &mut g, (&ns_id, &key_id), (&self.name, &key));
                     }
                 }
                 true
@@ -185,13 +333,7 @@ impl Tree {
 
     /// Live keys (expired entries excluded).
     pub fn keys(&self) -> Result<Vec<String>> {
-        let mut out = Vec::new();
-        for (k, e) in self.snapshot_entries(|_| true)? {
-            if self.inner.is_live(&self.name, &k, &e)? {
-                out.push(k);
-            }
-        }
-        Ok(out)
+        self.iter_keys()?.collect()
     }
 
     pub fn keys_sorted(&self) -> Result<Vec<String>> {
@@ -202,13 +344,7 @@ impl Tree {
 
     /// Entries whose key starts with `prefix` (decrypts each match).
     pub fn prefix<V: DeserializeOwned>(&self, prefix: &str) -> Result<Vec<(String, V)>> {
-        let mut out = Vec::new();
-        for (k, e) in self.snapshot_entries(|key| key.starts_with(prefix))? {
-            if let Some(v) = self.inner.read_value::<V>(&self.name, &k, &e)? {
-                out.push((k, v));
-            }
-        }
-        Ok(out)
+        self.prefix_iter(prefix)?.collect()
     }
 
     /// Visit every live entry; return `Break` to stop early.
@@ -217,21 +353,119 @@ impl Tree {
         V: DeserializeOwned,
         F: FnMut(&str, V) -> ControlFlow<()>,
     {
-        for (k, e) in self.snapshot_entries(|_| true)? {
-            if let Some(v) = self.inner.read_value::<V>(&self.name, &k, &e)? {
-                if let ControlFlow::Break(()) = f(&k, v) {
-                    break;
-                }
+        for item in self.iter()? {
+            let (key, value) = item?;
+            if let ControlFlow::Break(()) = f(&key, value) {
+                break;
             }
         }
         Ok(())
     }
 
+    /// Lazily iterate live entries in key order, decrypting one per step. The keys are
+    /// snapshotted now; each value is read when reached, so keys removed in the meantime
+    /// are skipped and keys added aren't visited. No lock is held between steps. In stores
+    /// with encrypted names, every name in the namespace is decrypted first to sort by.
+    pub fn iter<V: DeserializeOwned>(&self) -> Result<Iter<V>> {
+        self.prefix_iter("")
+    }
+
+    /// Lazily iterate live keys, with the same snapshot rules as [`Tree::iter`].
+    pub fn iter_keys(&self) -> Result<Keys> {
+        Ok(Keys::new(self.cursor("")?))
+    }
+
+    /// [`Tree::iter`], restricted to keys starting with `prefix`.
+    pub fn prefix_iter<V: DeserializeOwned>(&self, prefix: &str) -> Result<Iter<V>> {
+        Ok(Iter::new(self.cursor(prefix)?))
+    }
+
+    /// Lazily iterate live entries with keys in `range`, in key order; `.rev()` walks it
+    /// backwards. Same snapshot rules as [`Tree::iter`]. In stores with encrypted names,
+    /// this too decrypts every name in the namespace first, so it costs O(n) in the size
+    /// of the namespace however narrow the range (without holding the store's lock).
+    pub fn range<'r, V, R>(&self, range: R) -> Result<Iter<V>>
+    where
+        V: DeserializeOwned,
+        R: RangeBounds<&'r str>,
+    {
+        let cursor = Cursor::range(
+            Arc::clone(&self.inner),
+            &self.name,
+            range.start_bound().map(|k| *k),
+            range.end_bound().map(|k| *k),
+        )?;
+        Ok(Iter::new(cursor))
+    }
+
+    /// [`Tree::range`] from the first key at or after `key`. Like it, O(n) in the size of
+    /// the namespace in stores with encrypted names, where every name is decrypted to find
+    /// the starting point.
+    pub fn seek<V: DeserializeOwned>(&self, key: &str) -> Result<Iter<V>> {
+        self.range(key..)
+    }
+
+    /// The live entry with the smallest key.
+    pub fn first<V: DeserializeOwned>(&self) -> Result<Option<(String, V)>> {
+        let codec = self.inner.codec_by_name(&self.name)?;
+        edge(&self.inner, &self.name, false)?
+            .map(|first| decoded(codec, first))
+            .transpose()
+    }
+
+    /// The live entry with the largest key.
+    pub fn last<V: DeserializeOwned>(&self) -> Result<Option<(String, V)>> {
+        let codec = self.inner.codec_by_name(&self.name)?;
+        edge(&self.inner, &self.name, true)?
+            .map(|last| decoded(codec, last))
+            .transpose()
+    }
+
+    /// Subscribe to changes to `key`: every put, removal and sweep of it, as an [`Event`]
+    /// sent once the write is done. Events carry no values. Drop the receiver to
+    /// unsubscribe.
+    pub fn watch(&self, key: impl AsRef<[u8]>) -> Result<Receiver<Event>> {
+        let key = key_name(key.as_ref()).into_owned();
+        self.inner.subscribe(&self.name, Target::Key(key), false)
+    }
+
+    /// [`Tree::watch`] for every key starting with `prefix`.
+    pub fn watch_prefix(&self, prefix: &str) -> Result<Receiver<Event>> {
+        self.inner
+            .subscribe(&self.name, Target::Prefix(prefix.to_string()), false)
+    }
+
+    /// [`Tree::watch`], with puts carrying the value written (see [`Event::value`]). The
+    /// plaintext is copied into each event, and wiped when the event is dropped.
+    pub fn watch_with_values(&self, key: impl AsRef<[u8]>) -> Result<Receiver<Event>> {
+        let key = key_name(key.as_ref()).into_owned();
+        self.inner.subscribe(&self.name, Target::Key(key), true)
+    }
+
+    /// [`Tree::watch_prefix`], with values as [`Tree::watch_with_values`] has them.
+    pub fn watch_prefix_with_values(&self, prefix: &str) -> Result<Receiver<Event>> {
+        self.inner
+            .subscribe(&self.name, Target::Prefix(prefix.to_string()), true)
+    }
+
     pub fn clear(&self) -> Result<()> {
         self.inner.ensure_writable()?;
+        let ns_id = self.inner.ns_id(&self.name)?;
         {
             let mut g = self.inner.write_store()?;
-            if let Some(bucket) = g.get_mut(&self.name) {
+            let watched = self.inner.watching(&self.name);
+            if let Some(bucket) = g.get_mut(&ns_id) {
+                for (key_id, entry) in bucket.iter() {
+                    self.inner.touch(&ns_id, key_id, Some(entry));
+                    // an entry whose name can't be decrypted goes unreported
+                    if let Some((_, key)) = watched
+                        .then(|| self.inner.entry_names(&ns_id, key_id, entry).ok())
+                        .flatten()
+                    {
+                        self.inner
+                            .notify(&ns_id, (&self.name, &key), EventKind::Remove, None);
+                    }
+                }
                 bucket.clear();
             }
         }
@@ -239,23 +473,19 @@ impl Tree {
     }
 
     /// Decrypt the current value, with the store already locked.
-    fn load<V: DeserializeOwned>(&self, store: &Store, key: &str) -> Result<Option<V>> {
-        match fetch(store, &self.name, key) {
-            Some(e) => self.inner.read_value(&self.name, key, &e),
+    fn load<V: DeserializeOwned>(
+        &self,
+        store: &Store,
+        ns_id: &str,
+        key_id: &str,
+    ) -> Result<Option<V>> {
+        match fetch(store, ns_id, key_id) {
+            Some(e) => self.inner.read_value(ns_id, key_id, &e),
             None => Ok(None),
         }
     }
 
-    /// Clone matching entries so we can decrypt without holding the lock.
-    fn snapshot_entries<P: Fn(&str) -> bool>(&self, pred: P) -> Result<Vec<(String, Entry)>> {
-        let g = self.inner.read_store()?;
-        Ok(g.get(&self.name)
-            .map(|b| {
-                b.iter()
-                    .filter(|(k, _)| pred(k))
-                    .map(|(k, e)| (k.clone(), e.clone()))
-                    .collect()
-            })
-            .unwrap_or_default())
+    fn cursor(&self, prefix: &str) -> Result<Cursor> {
+        Cursor::new(Arc::clone(&self.inner), &self.name, prefix)
     }
 }
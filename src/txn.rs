@@ -5,10 +5,13 @@
 
 use serde::de::DeserializeOwned;
 use serde::Serialize;
+use zeroize::Zeroize;
 
+use crate::codec::encode_raw;
 use crate::error::{Error, Result};
 use crate::format::Store;
-use crate::store::{fetch, remove_from, seal_into, MicroKV};
+use crate::key::key_name;
+use crate::store::{fetch, remove_from, seal_encoded_into, seal_into, MicroKV};
 
 /// A batch of operations applied atomically by `MicroKV::transaction`.
 pub struct Txn<'a> {
@@ -21,22 +24,49 @@ impl<'a> Txn<'a> {
         Self { store, db }
     }
 
-    pub fn get<V: DeserializeOwned>(&self, ns: &str, key: &str) -> Result<Option<V>> {
-        match fetch(self.store, ns, key) {
-            Some(e) => self.db.inner.read_value(ns, key, &e),
+    pub fn get<V: DeserializeOwned>(&self, ns: &str, key: impl AsRef<[u8]>) -> Result<Option<V>> {
+        let (ns_id, key_id) = self.db.inner.locate(ns, &key_name(key.as_ref()))?;
+        match fetch(self.store, &ns_id, &key_id) {
+            Some(e) => self.db.inner.read_value(&ns_id, &key_id, &e),
             None => Ok(None),
         }
     }
 
-    pub fn require<V: DeserializeOwned>(&self, ns: &str, key: &str) -> Result<V> {
+    pub fn require<V: DeserializeOwned>(&self, ns: &str, key: impl AsRef<[u8]>) -> Result<V> {
         self.get(ns, key)?.ok_or(Error::KeyNotFound)
     }
 
-    pub fn put<V: Serialize>(&mut self, ns: &str, key: &str, value: &V) -> Result<()> {
-        seal_into(&self.db.inner, self.store, ns, key, value, None)
+    /// [`Tree::get_raw`](crate::Tree::get_raw) within the transaction.
+    pub fn get_raw(&self, ns: &str, key: impl AsRef<[u8]>) -> Result<Option<Vec<u8>>> {
+        let (ns_id, key_id) = self.db.inner.locate(ns, &key_name(key.as_ref()))?;
+        match fetch(self.store, &ns_id, &key_id) {
+            Some(e) => self.db.inner.read_raw(&ns_id, &key_id, &e),
+            None => Ok(None),
+        }
+    }
+
+    pub fn put<V: Serialize>(&mut self, ns: &str, key: impl AsRef<[u8]>, value: &V) -> Result<()> {
+        let key = key_name(key.as_ref());
+        seal_into(&self.db.inner, self.store, ns, &key, value, None)
+    }
+
+    /// [`Tree::put_raw`](crate::Tree::put_raw) within the transaction.
+    pub fn put_raw(&mut self, ns: &str, key: impl AsRef<[u8]>, value: &[u8]) -> Result<()> {
+        let key = key_name(key.as_ref());
+        let mut plaintext = encode_raw(value)?;
+        let result = seal_encoded_into(&self.db.inner, self.store, ns, &key, &plaintext, None);
+        plaintext.zeroize();
+        result
     }
 
-    pub fn remove(&mut self, ns: &str, key: &str) -> Result<bool> {
-        Ok(remove_from(self.store, ns, key))
+    pub fn remove(&mut self, ns: &str, key: impl AsRef<[u8]>) -> Result<bool> {
+        let key = key_name(key.as_ref());
+        let (ns_id, key_id) = self.db.inner.locate(ns, &key)?;
+        Ok(remove_from(
+            &self.db.inner,
+            self.store,
+            (&ns_id, &key_id),
+            (ns, &key),
+        ))
     }
 }
@@ -0,0 +1,371 @@
+//! [`TypedTree`]: a namespace bound to one key type and one value type.
+//!
+//! Keys are stored as the names [`KeyCodec`] encodes them to, which sort in the same order
+//! as the keys themselves, so ranges over a typed tree are ranges over its keys:
+//!
+//! * strings as they are;
+//! * integers as fixed-width lowercase hex (signed ones with the sign bit flipped, so
+//!   negatives sort first);
+//! * UUIDs hyphenated and lowercase (behind the `uuid` feature);
+//! * tuples component by component, every component but the last escaped (`\0` becomes
+//!   `\0\x01`) and terminated by `\0\0`.
+
+use std::marker::PhantomData;
+use std::ops::{Bound, ControlFlow, RangeBounds};
+use std::time::{Duration, SystemTime};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::error::{Error, Result};
+use crate::iter::Iter;
+use crate::key::{key_bytes, key_name};
+use crate::secret::Secret;
+use crate::tree::Tree;
+
+/// A key type for [`TypedTree`]: encodes to a name that sorts like the key.
+pub trait KeyCodec: Sized {
+    fn encode_key(&self) -> String;
+    /// Inverse of [`KeyCodec::encode_key`].
+    fn decode_key(name: &str) -> Result<Self>;
+}
+
+fn bad_key<K>(name: &str) -> Error {
+    Error::Serialization(format!(
+        "key {name:?} isn't a {}",
+        std::any::type_name::<K>()
+    ))
+}
+
+impl KeyCodec for String {
+    fn encode_key(&self) -> String {
+        self.clone()
+    }
+
+    fn decode_key(name: &str) -> Result<Self> {
+        Ok(name.to_string())
+    }
+}
+
+macro_rules! unsigned_key {
+    ($($t:ty),*) => {$(
+        impl KeyCodec for $t {
+            fn encode_key(&self) -> String {
+                format!("{:0width$x}", self, width = 2 * std::mem::size_of::<$t>())
+            }
+
+            fn decode_key(name: &str) -> Result<Self> {
+                let fixed = name.len() == 2 * std::mem::size_of::<$t>()
+                    && name.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'));
+                fixed
+                    .then(|| <$t>::from_str_radix(name, 16).ok())
+                    .flatten()
+                    .ok_or_else(|| bad_key::<$t>(name))
+            }
+        }
+    )*};
+}
+
+macro_rules! signed_key {
+    ($($t:ty => $u:ty),*) => {$(
+        impl KeyCodec for $t {
+            fn encode_key(&self) -> String {
+                ((*self as $u) ^ (1 << (<$u>::BITS - 1))).encode_key()
+            }
+
+            fn decode_key(name: &str) -> Result<Self> {
+                let flipped = <$u>::decode_key(name).map_err(|_| bad_key::<$t>(name))?;
+                Ok((flipped ^ (1 << (<$u>::BITS - 1))) as $t)
+            }
+        }
+    )*};
+}
+
+unsigned_key!(u8, u16, u32, u64, u128);
+signed_key!(i8 => u8, i16 => u16, i32 => u32, i64 => u64, i128 => u128);
+
+#[cfg(feature = "uuid")]
+impl KeyCodec for uuid::Uuid {
+    fn encode_key(&self) -> String {
+        self.hyphenated().to_string()
+    }
+
+    fn decode_key(name: &str) -> Result<Self> {
+        let id = uuid::Uuid::try_parse(name).map_err(|_| bad_key::<Self>(name))?;
+        // only the canonical form, so every key has one name
+        if id.encode_key() != name {
+            return Err(bad_key::<Self>(name));
+        }
+        Ok(id)
+    }
+}
+
+/// Append a non-final tuple component, escaped and terminated.
+fn push_component(out: &mut String, name: &str) {
+    for c in name.chars() {
+        out.push(c);
+        if c == '\0' {
+            out.push('\u{1}');
+        }
+    }
+    out.push_str("\0\0");
+}
+
+/// Split off a component written by [`push_component`], unescaped.
+fn take_component(rest: &mut &str) -> Option<String> {
+    let mut out = String::new();
+    let mut chars = rest.char_indices();
+    while let Some((_, c)) = chars.next() {
+        if c != '\0' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some((_, '\u{1}')) => out.push('\0'),
+            Some((at, '\0')) => {
+                *rest = &rest[at + 1..];
+                return Some(out);
+            }
+            _ => return None,
+        }
+    }
+    None
+}
+
+macro_rules! tuple_key {
+    ($($head:ident),+ ; $last:ident) => {
+        impl<$($head: KeyCodec,)+ $last: KeyCodec> KeyCodec for ($($head,)+ $last) {
+            #[allow(non_snake_case)]
+            fn encode_key(&self) -> String {
+                let ($($head,)+ $last) = self;
+                let mut out = String::new();
+                $(push_component(&mut out, &$head.encode_key());)+
+                out.push_str(&$last.encode_key());
+                out
+            }
+
+            fn decode_key(name: &str) -> Result<Self> {
+                let mut rest = name;
+                Ok((
+                    $($head::decode_key(
+                        &take_component(&mut rest).ok_or_else(|| bad_key::<Self>(name))?,
+                    )?,)+
+                    $last::decode_key(rest)?,
+                ))
+            }
+        }
+    };
+}
+
+tuple_key!(A; B);
+tuple_key!(A, B; C);
+tuple_key!(A, B, C; D);
+
+/// A handle to one namespace whose keys are all `K` and values all `V`; see
+/// [`MicroKV::typed_namespace`](crate::MicroKV::typed_namespace). Each method is the
+/// [`Tree`] method of the same name.
+pub struct TypedTree<K, V> {
+    tree: Tree,
+    _types: PhantomData<fn(K) -> V>,
+}
+
+impl<K, V> Clone for TypedTree<K, V> {
+    fn clone(&self) -> Self {
+        TypedTree::new(self.tree.clone())
+    }
+}
+
+impl<K, V> TypedTree<K, V> {
+    pub(crate) fn new(tree: Tree) -> Self {
+        TypedTree {
+            tree,
+            _types: PhantomData,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        self.tree.name()
+    }
+
+    /// The same namespace, untyped. Keys appear under their encoded names.
+    pub fn untyped(&self) -> &Tree {
+        &self.tree
+    }
+}
+
+impl<K, V> TypedTree<K, V>
+where
+    K: KeyCodec,
+    V: Serialize + DeserializeOwned,
+{
+    pub fn get(&self, key: &K) -> Result<Option<V>> {
+        self.tree.get(key.encode_key())
+    }
+
+    pub fn require(&self, key: &K) -> Result<V> {
+        self.tree.require(key.encode_key())
+    }
+
+    pub fn get_secret(&self, key: &K) -> Result<Option<Secret<V>>> {
+        self.tree.get_secret(key.encode_key())
+    }
+
+    pub fn put(&self, key: &K, value: &V) -> Result<()> {
+        self.tree.put(key.encode_key(), value)
+    }
+
+    pub fn put_with_ttl(&self, key: &K, value: &V, ttl: Duration) -> Result<()> {
+        self.tree.put_with_ttl(key.encode_key(), value, ttl)
+    }
+
+    pub fn ttl(&self, key: &K) -> Result<Option<Duration>> {
+        self.tree.ttl(key.encode_key())
+    }
+
+    pub fn expire(&self, key: &K, ttl: Duration) -> Result<bool> {
+        self.tree.expire(key.encode_key(), ttl)
+    }
+
+    pub fn expire_at(&self, key: &K, at: SystemTime) -> Result<bool> {
+        self.tree.expire_at(key.encode_key(), at)
+    }
+
+    pub fn persist(&self, key: &K) -> Result<bool> {
+        self.tree.persist(key.encode_key())
+    }
+
+    pub fn touch(&self, key: &K, ttl: Duration) -> Result<bool> {
+        self.tree.touch(key.encode_key(), ttl)
+    }
+
+    pub fn remove(&self, key: &K) -> Result<bool> {
+        self.tree.remove(key.encode_key())
+    }
+
+    pub fn contains(&self, key: &K) -> Result<bool> {
+        self.tree.contains(key.encode_key())
+    }
+
+    pub fn len(&self) -> Result<usize> {
+        self.tree.len()
+    }
+
+    pub fn is_empty(&self) -> Result<bool> {
+        self.tree.is_empty()
+    }
+
+    pub fn update<F>(&self, key: &K, f: F) -> Result<()>
+    where
+        F: FnOnce(Option<V>) -> Option<V>,
+    {
+        self.tree.update(key.encode_key(), f)
+    }
+
+    pub fn get_or_insert_with<F>(&self, key: &K, f: F) -> Result<V>
+    where
+        F: FnOnce() -> V,
+    {
+        self.tree.get_or_insert_with(key.encode_key(), f)
+    }
+
+    pub fn compare_and_swap(&self, key: &K, expected: Option<&V>, new: Option<&V>) -> Result<bool> {
+        self.tree.compare_and_swap(key.encode_key(), expected, new)
+    }
+
+    /// Live keys, in key order. Values aren't decoded.
+    pub fn keys(&self) -> Result<Vec<K>> {
+        self.tree
+            .iter_keys()?
+            .map(|name| decode_name(&name?))
+            .collect()
+    }
+
+    pub fn for_each<F>(&self, mut f: F) -> Result<()>
+    where
+        F: FnMut(&K, V) -> ControlFlow<()>,
+    {
+        for item in self.iter()? {
+            let (key, value) = item?;
+            if let ControlFlow::Break(()) = f(&key, value) {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Lazily iterate live entries in key order, as [`Tree::range`] does.
+    pub fn iter(&self) -> Result<TypedIter<K, V>> {
+        self.range(..)
+    }
+
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> Result<TypedIter<K, V>> {
+        let encode = |bound: Bound<&K>| bound.map(stored_name);
+        let (lo, hi) = (encode(range.start_bound()), encode(range.end_bound()));
+        let bounds = (
+            lo.as_ref().map(String::as_str),
+            hi.as_ref().map(String::as_str),
+        );
+        Ok(TypedIter::new(self.tree.range(bounds)?))
+    }
+
+    pub fn seek(&self, key: &K) -> Result<TypedIter<K, V>> {
+        Ok(TypedIter::new(self.tree.seek(&stored_name(key))?))
+    }
+
+    pub fn first(&self) -> Result<Option<(K, V)>> {
+        self.tree.first()?.map(decode_entry).transpose()
+    }
+
+    pub fn last(&self) -> Result<Option<(K, V)>> {
+        self.tree.last()?.map(decode_entry).transpose()
+    }
+
+    pub fn clear(&self) -> Result<()> {
+        self.tree.clear()
+    }
+}
+
+/// The name `key` is stored under (see [`key_name`]), for bounds: both orders agree.
+fn stored_name<K: KeyCodec>(key: &K) -> String {
+    key_name(key.encode_key().as_bytes()).into_owned()
+}
+
+/// Back from a stored name (which is escaped if it starts with NUL) to the key.
+fn decode_name<K: KeyCodec>(name: &str) -> Result<K> {
+    let name = String::from_utf8(key_bytes(name)).map_err(|_| bad_key::<K>(name))?;
+    K::decode_key(&name)
+}
+
+fn decode_entry<K: KeyCodec, V>((name, value): (String, V)) -> Result<(K, V)> {
+    Ok((decode_name(&name)?, value))
+}
+
+/// Live entries of a [`TypedTree`]; see [`TypedTree::iter`]. A name that doesn't decode as
+/// a `K` (say, written through [`TypedTree::untyped`]) yields an `Err`.
+pub struct TypedIter<K, V> {
+    inner: Iter<V>,
+    _key: PhantomData<fn() -> K>,
+}
+
+impl<K, V> TypedIter<K, V> {
+    fn new(inner: Iter<V>) -> Self {
+        TypedIter {
+            inner,
+            _key: PhantomData,
+        }
+    }
+}
+
+impl<K: KeyCodec, V: DeserializeOwned> Iterator for TypedIter<K, V> {
+    type Item = Result<(K, V)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.inner.next()?.and_then(decode_entry))
+    }
+}
+
+impl<K: KeyCodec, V: DeserializeOwned> DoubleEndedIterator for TypedIter<K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        Some(self.inner.next_back()?.and_then(decode_entry))
+    }
+}
@@ -0,0 +1,163 @@
+//! Checking every entry, and the report a [`Config::salvage`](crate::Config::salvage)
+//! open leaves behind.
+
+use std::io::Read;
+
+use serde::de::DeserializeOwned;
+
+use crate::config::KdfRepr;
+use crate::crypto::SALT_LEN;
+use crate::error::Result;
+use crate::format::{Entry, Store, StoreFile};
+use crate::store::{Inner, MicroKV};
+
+/// What [`MicroKV::verify`] found, or what a [`Config::salvage`](crate::Config::salvage)
+/// open set aside.
+#[derive(Debug, Clone, Default)]
+pub struct VerifyReport {
+    /// Entries checked.
+    pub checked: usize,
+    /// Entries that failed authentication (and, after a salvage open, were dropped).
+    pub bad: Vec<BadEntry>,
+    /// The entries didn't match the store's integrity tag (salvage opens only).
+    pub integrity_failed: bool,
+    /// Journal records from some point on failed authentication, and were dropped
+    /// (salvage opens only).
+    pub journal_truncated: bool,
+    /// The store file didn't decode as a whole, and entries from some point on were
+    /// dropped (salvage opens only).
+    pub lost: Option<LostEntries>,
+}
+
+impl VerifyReport {
+    /// Nothing was wrong.
+    pub fn is_clean(&self) -> bool {
+        self.bad.is_empty()
+            && !self.integrity_failed
+            && !self.journal_truncated
+            && self.lost.is_none()
+    }
+}
+
+/// Where a damaged store file stopped decoding. Entries are read in file order, so
+/// everything from here to the end of the entries was lost.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LostEntries {
+    /// Offset in the file of the first entry that couldn't be decoded.
+    pub offset: usize,
+    /// Bytes of entries dropped from there.
+    pub len: usize,
+    /// The namespace decoding stopped in (its blind index, with encrypted names), if its
+    /// name could be read. Namespaces after it in the file were lost whole.
+    pub namespace: Option<String>,
+}
+
+/// An entry that failed authentication. In stores with encrypted names, the names are
+/// sealed inside the entry, so these are the blind indexes it's stored under instead.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BadEntry {
+    pub namespace: String,
+    pub key: String,
+}
+
+impl MicroKV {
+    /// Authenticate every entry, expired or not, reporting each one that fails rather
+    /// than stopping at the first.
+    pub fn verify(&self) -> Result<VerifyReport> {
+        let store = self.inner.read_store()?;
+        Ok(check(&self.inner, &store))
+    }
+
+    /// What a [`Config::salvage`](crate::Config::salvage) open dropped; `None` for other
+    /// opens. Save the store to make the repair permanent.
+    pub fn salvage_report(&self) -> Option<&VerifyReport> {
+        self.inner.salvaged.get()
+    }
+}
+
+/// Authenticate every entry in `store`.
+pub(crate) fn check(inner: &Inner, store: &Store) -> VerifyReport {
+    let mut report = VerifyReport::default();
+    for (ns_id, bucket) in store.iter() {
+        for (key_id, entry) in bucket.iter() {
+            report.checked += 1;
+            let ok = inner
+                .unseal(ns_id, key_id, entry)
+                .is_ok_and(|frame| frame.names.is_some() || !inner.encrypted_names());
+            if !ok {
+                report.bad.push(BadEntry {
+                    namespace: ns_id.clone(),
+                    key: key_id.clone(),
+                });
+            }
+        }
+    }
+    report
+}
+
+/// Decode a store file that doesn't decode as a whole: the fields before the entries, then
+/// the fields after them (the shortest tail of the file that completes it), then the
+/// entries one by one until one fails. `None` if the fields around the entries can't be
+/// found, since there's no opening the store without them.
+pub(crate) fn decode_damaged(raw: &[u8]) -> Option<(StoreFile, Option<LostEntries>)> {
+    let mut rd = raw;
+    rmp::decode::read_array_len(&mut rd).ok()?;
+    next::<String>(&mut rd)?;
+    next::<u8>(&mut rd)?;
+    next::<KdfRepr>(&mut rd)?;
+    next::<[u8; SALT_LEN]>(&mut rd)?;
+    next::<Entry>(&mut rd)?;
+    let (head, body) = raw.split_at(raw.len() - rd.len());
+
+    // the head, no entries, and a tail: a whole file if it decodes to the last byte
+    let (mut sf, entries) = (0..=body.len()).rev().find_map(|at| {
+        let mut file = head.chain(&[0x80][..]).chain(&body[at..]);
+        let sf: StoreFile = next(&mut file)?;
+        let rest = file.read(&mut [0]).ok()?;
+        (rest == 0).then(|| (sf, &body[..at]))
+    })?;
+
+    let mut rd = entries;
+    let mut namespace = None;
+    read_entries(&mut rd, &mut sf.trees, &mut namespace);
+    let lost = (!rd.is_empty()).then(|| LostEntries {
+        offset: head.len() + entries.len() - rd.len(),
+        len: rd.len(),
+        namespace,
+    });
+    Some((sf, lost))
+}
+
+/// Read buckets into `trees` until the bytes run out or stop decoding, leaving `rd` at
+/// the first pair that didn't decode and `namespace` naming its bucket.
+fn read_entries(rd: &mut &[u8], trees: &mut Store, namespace: &mut Option<String>) {
+    let Ok(buckets) = rmp::decode::read_map_len(rd) else {
+        return;
+    };
+    for _ in 0..buckets {
+        let mut at = *rd;
+        let Some(ns) = next::<String>(&mut at) else {
+            return;
+        };
+        *namespace = Some(ns.clone());
+        let Ok(len) = rmp::decode::read_map_len(&mut at) else {
+            return;
+        };
+        *rd = at;
+        let bucket = trees.entry(ns).or_default();
+        for _ in 0..len {
+            let mut at = *rd;
+            let (Some(key), Some(entry)) = (next::<String>(&mut at), next::<Entry>(&mut at)) else {
+                return;
+            };
+            bucket.insert(key, entry);
+            *rd = at;
+        }
+        *namespace = None;
+    }
+}
+
+/// The next value in `rd`, reading no further than its end.
+fn next<T: DeserializeOwned>(rd: &mut impl Read) -> Option<T> {
+    rmp_serde::from_read(rd).ok()
+}
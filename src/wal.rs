@@ -0,0 +1,219 @@
+//! Append-only write-ahead log: sealed batches of entry mutations appended to the
+//! backend's journal (a `<path>.wal` sidecar for files) between full rewrites of the base.
+//!
+//! Layout: `WAL_MAGIC ++ log_id_le`, then records of `len_le32 ++ msgpack(Entry)`. Each
+//! record seals one flushed batch of [`LogOp`]s, bound to the log id (which the base file
+//! carries too) and its sequence number, so records can't be reordered, dropped from the
+//! middle, or replayed against a different base. A short final record is a torn append
+//! and is ignored.
+
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroize;
+
+use crate::backend::Backend;
+use crate::crypto::{aead_decrypt, aead_encrypt, AeadCipher};
+use crate::error::{Error, Result};
+use crate::format::{Entry, Integrity, Store, Tally};
+
+const WAL_MAGIC: &[u8; 8] = b"mkv-wal\0";
+const HEADER_LEN: usize = WAL_MAGIC.len() + 8;
+
+/// Logs smaller than this are never compacted, however small the base file.
+const COMPACT_MIN_LEN: u64 = 64 * 1024;
+
+/// A logged mutation, carrying the entry's final state as of the flush.
+#[derive(Serialize, Deserialize)]
+pub(crate) enum LogOp {
+    Put {
+        ns: String,
+        key: String,
+        entry: Entry,
+    },
+    Remove {
+        ns: String,
+        key: String,
+    },
+}
+
+/// One flushed batch, with the store's [`Integrity`] once it's applied.
+#[derive(Serialize, Deserialize)]
+struct LogRecord {
+    ops: Vec<LogOp>,
+    integrity: Option<Integrity>,
+}
+
+/// What [`replay`] found.
+#[derive(Default)]
+pub(crate) struct Replay {
+    /// Sequence number of the next record.
+    pub(crate) seq: u64,
+    /// Bytes of valid log.
+    pub(crate) len: u64,
+    /// From the last record applied, superseding the base file's.
+    pub(crate) integrity: Option<Integrity>,
+    /// A salvaging replay stopped at a record that failed authentication.
+    pub(crate) truncated: bool,
+}
+
+/// Keys written since the last flush; their current state is what gets logged, so a
+/// key touched many times (or by a rolled-back transaction) costs one op.
+#[derive(Default)]
+pub(crate) struct Journal {
+    /// Each with the nonce it was last flushed with (`None` if it didn't exist then).
+    pub(crate) touched: IndexMap<(String, String), Option<Vec<u8>>>,
+    /// The integrity tally as of the last flush; `None` until one is computed, or once a
+    /// store-wide change means it has to be again.
+    pub(crate) tally: Option<Tally>,
+    /// Set by store-wide changes (e.g. rekey) that only a full rewrite can persist.
+    pub(crate) rewrite: bool,
+}
+
+/// Log position, paired with the base file it extends.
+#[derive(Default)]
+pub(crate) struct Wal {
+    /// Shared with the base file; `0` means there is no log to append to.
+    pub(crate) log_id: u64,
+    /// Sequence number of the next record.
+    pub(crate) seq: u64,
+    /// Bytes of valid log (header + complete records); a torn tail past this is dropped.
+    pub(crate) len: u64,
+    /// Size of the base file at its last rewrite; the log is compacted once it outgrows it.
+    pub(crate) base_len: u64,
+}
+
+impl Wal {
+    /// The log has outgrown the base file: replaying it costs more than a rewrite.
+    pub(crate) fn needs_compaction(&self) -> bool {
+        self.len > self.base_len.max(COMPACT_MIN_LEN)
+    }
+}
+
+/// AAD binding a record to its log and position.
+fn record_aad(log_id: u64, seq: u64) -> Vec<u8> {
+    let mut aad = b"microkv/wal".to_vec();
+    aad.extend_from_slice(&log_id.to_le_bytes());
+    aad.extend_from_slice(&seq.to_le_bytes());
+    aad
+}
+
+/// Apply the backend's log to `store`. A missing log, or one left over from an older base
+/// (different id), replays nothing. A record that fails authentication is an error, or
+/// with `salvage`, where replay stops.
+pub(crate) fn replay(
+    backend: &dyn Backend,
+    log_id: u64,
+    cipher: &AeadCipher,
+    store: &mut Store,
+    salvage: bool,
+) -> Result<Replay> {
+    if log_id == 0 {
+        return Ok(Replay::default());
+    }
+    let Some(raw) = backend.load_journal()? else {
+        return Ok(Replay::default());
+    };
+    if raw.len() < HEADER_LEN
+        || &raw[..WAL_MAGIC.len()] != WAL_MAGIC
+        || raw[WAL_MAGIC.len()..HEADER_LEN] != log_id.to_le_bytes()
+    {
+        return Ok(Replay::default());
+    }
+
+    let mut pos = HEADER_LEN;
+    let mut seq = 0u64;
+    let mut integrity = None;
+    let mut truncated = false;
+    while raw.len() - pos >= 4 {
+        let mut len = [0u8; 4];
+        len.copy_from_slice(&raw[pos..pos + 4]);
+        let len = u32::from_le_bytes(len) as usize;
+        if raw.len() - pos - 4 < len {
+            break; // torn append
+        }
+        let decoded = match open_record(cipher, log_id, seq, &raw[pos + 4..pos + 4 + len]) {
+            Ok(decoded) => decoded,
+            Err(_) if salvage => {
+                truncated = true;
+                break;
+            }
+            Err(e) => return Err(e),
+        };
+        for op in decoded.ops {
+            apply(store, op);
+        }
+        integrity = decoded.integrity;
+        pos += 4 + len;
+        seq += 1;
+    }
+    Ok(Replay {
+        seq,
+        len: pos as u64,
+        integrity,
+        truncated,
+    })
+}
+
+fn open_record(cipher: &AeadCipher, log_id: u64, seq: u64, body: &[u8]) -> Result<LogRecord> {
+    let record: Entry = rmp_serde::from_slice(body)
+        .map_err(|e| Error::CorruptStore(format!("cannot deserialize journal record: {e}")))?;
+    let mut plaintext = aead_decrypt(
+        cipher,
+        &record_aad(log_id, seq),
+        &record.nonce,
+        &record.data,
+    )
+    .map_err(|_| Error::CorruptStore("journal record failed authentication".to_string()))?;
+    let decoded = rmp_serde::from_slice(&plaintext)
+        .map_err(|e| Error::CorruptStore(format!("cannot deserialize journal record: {e}")));
+    plaintext.zeroize();
+    decoded
+}
+
+fn apply(store: &mut Store, op: LogOp) {
+    match op {
+        LogOp::Put { ns, key, entry } => {
+            store.entry(ns).or_default().insert(key, entry);
+        }
+        LogOp::Remove { ns, key } => {
+            if let Some(bucket) = store.get_mut(&ns) {
+                bucket.remove(&key);
+            }
+        }
+    }
+}
+
+/// Seal `ops` (and the integrity tag covering them) as the next record and append it
+/// durably, starting a fresh log (header first) if there is none yet. Any torn tail from
+/// an earlier crash is truncated away.
+pub(crate) fn append(
+    backend: &dyn Backend,
+    wal: &mut Wal,
+    cipher: &AeadCipher,
+    ops: Vec<LogOp>,
+    integrity: Option<Integrity>,
+) -> Result<()> {
+    let mut plaintext = rmp_serde::to_vec(&LogRecord { ops, integrity })
+        .map_err(|e| Error::Serialization(e.to_string()))?;
+    let sealed = aead_encrypt(cipher, &record_aad(wal.log_id, wal.seq), &plaintext);
+    plaintext.zeroize();
+    let (nonce, data) = sealed?;
+    let body = rmp_serde::to_vec(&Entry::new((nonce, data)))
+        .map_err(|e| Error::Serialization(e.to_string()))?;
+    let len = u32::try_from(body.len())
+        .map_err(|_| Error::Serialization("journal record too large".to_string()))?;
+
+    let mut buf = Vec::with_capacity(HEADER_LEN + 4 + body.len());
+    if wal.len == 0 {
+        buf.extend_from_slice(WAL_MAGIC);
+        buf.extend_from_slice(&wal.log_id.to_le_bytes());
+    }
+    buf.extend_from_slice(&len.to_le_bytes());
+    buf.extend_from_slice(&body);
+
+    backend.append_journal(wal.len, &buf)?;
+
+    wal.len += buf.len() as u64;
+    wal.seq += 1;
+    Ok(())
+}
@@ -0,0 +1,174 @@
+//! Change subscriptions: [`Tree::watch`](crate::Tree::watch) and friends.
+//!
+//! Writes record an [`Event`] for each key they change while they hold the store's write
+//! lock, and the events are sent once it's released, in the order the writes happened. A
+//! transaction's events are sent when it commits, and dropped if it rolls back. Events
+//! carry names only, unless the subscription asked for values.
+
+use std::sync::mpsc::{self, Receiver, Sender};
+
+use serde::de::DeserializeOwned;
+use zeroize::Zeroizing;
+
+use crate::codec::decode_raw;
+use crate::config::Codec;
+use crate::error::Result;
+
+/// What happened to a key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    /// A value was written.
+    Put,
+    /// The key was removed (including by [`Tree::clear`](crate::Tree::clear)).
+    Remove,
+    /// An expired entry was dropped by [`MicroKV::sweep_expired`](crate::MicroKV::sweep_expired).
+    Expire,
+    /// Only the key's expiry changed ([`Tree::expire`](crate::Tree::expire) and friends).
+    /// The value is the same, so it isn't sent.
+    Ttl,
+}
+
+/// A change to one key, sent to the subscriptions watching it. Keys are the names entries
+/// are stored under, as [`Tree::keys`](crate::Tree::keys) returns them.
+#[derive(Clone)]
+pub struct Event {
+    pub namespace: String,
+    pub key: String,
+    pub kind: EventKind,
+    /// The encoded value, for puts seen by subscriptions that asked for it.
+    value: Option<Zeroizing<Vec<u8>>>,
+    codec: Codec,
+}
+
+impl Event {
+    /// The value a [`EventKind::Put`] wrote, if the subscription was made with values
+    /// ([`Tree::watch_with_values`](crate::Tree::watch_with_values)); `None` otherwise.
+    pub fn value<V: DeserializeOwned>(&self) -> Result<Option<V>> {
+        self.value
+            .as_ref()
+            .map(|v| self.codec.decode(v.to_vec()))
+            .transpose()
+    }
+
+    /// [`Event::value`] for a value written by [`Tree::put_raw`](crate::Tree::put_raw).
+    pub fn value_raw(&self) -> Result<Option<Vec<u8>>> {
+        self.value
+            .as_ref()
+            .map(|v| decode_raw(v.to_vec()))
+            .transpose()
+    }
+}
+
+// by hand, so a value can't end up in logs
+impl std::fmt::Debug for Event {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Event")
+            .field("namespace", &self.namespace)
+            .field("key", &self.key)
+            .field("kind", &self.kind)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The keys a subscription covers.
+pub(crate) enum Target {
+    Key(String),
+    Prefix(String),
+}
+
+struct Subscription {
+    namespace: String,
+    target: Target,
+    values: bool,
+    tx: Sender<Event>,
+}
+
+impl Subscription {
+    fn matches(&self, ns: &str, key: &str) -> bool {
+        self.namespace == ns
+            && match &self.target {
+                Target::Key(k) => k == key,
+                Target::Prefix(p) => key.starts_with(p.as_str()),
+            }
+    }
+}
+
+/// Subscriptions, and the events recorded but not yet sent.
+#[derive(Default)]
+pub(crate) struct Watchers {
+    subs: Vec<Subscription>,
+    pending: Vec<Event>,
+}
+
+impl Watchers {
+    pub(crate) fn subscribe(&mut self, ns: &str, target: Target, values: bool) -> Receiver<Event> {
+        let (tx, rx) = mpsc::channel();
+        self.subs.push(Subscription {
+            namespace: ns.to_string(),
+            target,
+            values,
+            tx,
+        });
+        rx
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.subs.is_empty()
+    }
+
+    /// Whether any subscription covers keys in `ns`.
+    pub(crate) fn watching(&self, ns: &str) -> bool {
+        self.subs.iter().any(|s| s.namespace == ns)
+    }
+
+    /// `None` if nothing watches `(ns, key)`, else whether any subscription wants values.
+    pub(crate) fn interest(&self, ns: &str, key: &str) -> Option<bool> {
+        self.subs
+            .iter()
+            .filter(|s| s.matches(ns, key))
+            .map(|s| s.values)
+            .reduce(|a, b| a || b)
+    }
+
+    pub(crate) fn record(
+        &mut self,
+        (ns, key): (&str, &str),
+        kind: EventKind,
+        value: Option<&[u8]>,
+        codec: Codec,
+    ) {
+        self.pending.push(Event {
+            namespace: ns.to_string(),
+            key: key.to_string(),
+            kind,
+            value: value.map(|v| Zeroizing::new(v.to_vec())),
+            codec,
+        });
+    }
+
+    /// How many events are pending, to [`Watchers::rollback`] to.
+    pub(crate) fn mark(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Drop the events recorded since `mark`.
+    pub(crate) fn rollback(&mut self, mark: usize) {
+        self.pending.truncate(mark);
+    }
+
+    /// Send every pending event, forgetting subscriptions whose receiver is gone.
+    pub(crate) fn dispatch(&mut self) {
+        for event in std::mem::take(&mut self.pending) {
+            self.subs.retain(|s| {
+                if !s.matches(&event.namespace, &event.key) {
+                    return true;
+                }
+                let mut event = event.clone();
+                if !s.values {
+                    event.value = None;
+                }
+                s.tx.send(event).is_ok()
+            });
+        }
+    }
+}
@@ -0,0 +1,217 @@
+//! Integration tests for the `microkv` command-line tool.
+
+#![cfg(feature = "cli")]
+
+use std::env;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+use microkv::{Config, Credential, KdfParams, MicroKV};
+
+static PASSWORD: &str = "correct horse battery staple";
+static ACK: &str = "--i-understand-this-is-unencrypted";
+
+/// A fresh store at a temp path, made with a cheap KDF so each invocation opens it quickly.
+fn store(name: &str) -> PathBuf {
+    let mut p = env::temp_dir();
+    p.push(format!("microkv_cli_{name}.kv"));
+    let _ = std::fs::remove_file(&p);
+    let _ = std::fs::remove_file(format!("{}.lock", p.display()));
+    let config = Config {
+        kdf: KdfParams::scrypt(10, 8, 1),
+        ..Default::default()
+    };
+    let db = MicroKV::open_with(&p, Credential::password(PASSWORD), config).unwrap();
+    db.save().unwrap();
+    p
+}
+
+fn microkv(store: &Path) -> Command {
+    let mut cmd = Command::new(env!("CARGO_BIN_EXE_microkv"));
+    cmd.arg("--store").arg(store).env_remove("MICROKV_STORE");
+    cmd
+}
+
+/// Runs with the password in `MICROKV_PASSWORD` and `stdin` on standard input.
+fn run(store: &Path, args: &[&str], stdin: &[u8]) -> Output {
+    let mut cmd = microkv(store);
+    cmd.args(["--password-env", "MICROKV_PASSWORD"])
+        .env("MICROKV_PASSWORD", PASSWORD)
+        .args(args);
+    feed(cmd, stdin)
+}
+
+fn feed(mut cmd: Command, stdin: &[u8]) -> Output {
+    let mut child = cmd
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // a refused command may exit before reading its input
+    let _ = child.stdin.take().unwrap().write_all(stdin);
+    child.wait_with_output().unwrap()
+}
+
+fn ok(out: Output) -> String {
+    assert!(
+        out.status.success(),
+        "microkv failed: {}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    String::from_utf8(out.stdout).unwrap()
+}
+
+#[test]
+fn put_get_ls_rm() {
+    let p = store("put_get_ls_rm");
+    ok(run(&p, &["put", "name", "test"], b""));
+    ok(run(
+        &p,
+        &["-n", "users", "put", "alice", "42", "--json"],
+        b"",
+    ));
+    ok(run(
+        &p,
+        &["-n", "users", "put", "bob", "{\"admin\":true}", "--json"],
+        b"",
+    ));
+
+    assert_eq!(ok(run(&p, &["get", "name"], b"")), "\"test\"\n");
+    assert_eq!(ok(run(&p, &["-n", "users", "get", "alice"], b"")), "42\n");
+    assert_eq!(ok(run(&p, &["-n", "users", "ls"], b"")), "alice\nbob\n");
+    assert_eq!(ok(run(&p, &["namespaces"], b"")), "\nusers\n");
+
+    ok(run(&p, &["-n", "users", "rm", "alice"], b""));
+    assert!(!run(&p, &["-n", "users", "get", "alice"], b"")
+        .status
+        .success());
+    assert!(!run(&p, &["-n", "users", "rm", "alice"], b"")
+        .status
+        .success());
+
+    // the library sees what the tool wrote
+    let db = MicroKV::open_existing(&p, Credential::password(PASSWORD)).unwrap();
+    assert_eq!(db.get::<String>("name").unwrap().as_deref(), Some("test"));
+}
+
+#[test]
+fn put_stores_strings_unless_json() {
+    let p = store("put_strings");
+    ok(run(&p, &["put", "pin", "007"], b""));
+    ok(run(&p, &["put", "count", "1234"], b""));
+    ok(run(&p, &["put", "typed", "1234", "--json"], b""));
+    assert_eq!(ok(run(&p, &["get", "pin"], b"")), "\"007\"\n");
+    assert_eq!(ok(run(&p, &["get", "count"], b"")), "\"1234\"\n");
+    assert_eq!(ok(run(&p, &["get", "typed"], b"")), "1234\n");
+
+    // not JSON: refused rather than quietly stored as a string
+    assert!(!run(&p, &["put", "bad", "{oops", "--json"], b"")
+        .status
+        .success());
+    assert!(!run(&p, &["get", "bad"], b"").status.success());
+}
+
+#[test]
+fn export_import_namespace() {
+    let p = store("export_import");
+    ok(run(&p, &["put", "a", "1", "--json"], b""));
+    ok(run(&p, &["put", "b", "two"], b""));
+    let dump = ok(run(&p, &["export", ACK], b""));
+
+    let q = store("export_import_into");
+    ok(run(&q, &["-n", "copy", "import", ACK], dump.as_bytes()));
+    assert_eq!(ok(run(&q, &["-n", "copy", "get", "a"], b"")), "1\n");
+    assert_eq!(ok(run(&q, &["-n", "copy", "get", "b"], b"")), "\"two\"\n");
+}
+
+#[test]
+fn export_import_all() {
+    let p = store("export_import_all");
+    ok(run(&p, &["put", "a", "1", "--json"], b""));
+    ok(run(&p, &["-n", "users", "put", "alice", "admin"], b""));
+    let file = env::temp_dir().join("microkv_cli_export_import_all.json");
+    std::fs::write(&file, ok(run(&p, &["export", "--all", ACK], b""))).unwrap();
+
+    let q = store("export_import_all_into");
+    ok(run(
+        &q,
+        &["import", "--all", ACK, file.to_str().unwrap()],
+        b"",
+    ));
+    assert_eq!(ok(run(&q, &["get", "a"], b"")), "1\n");
+    assert_eq!(
+        ok(run(&q, &["-n", "users", "get", "alice"], b"")),
+        "\"admin\"\n"
+    );
+}
+
+#[test]
+fn dumps_need_the_unencrypted_flag() {
+    let p = store("dumps_need_flag");
+    ok(run(&p, &["put", "a", "secret"], b""));
+    for args in [&["export"][..], &["export", "--all"]] {
+        let out = run(&p, args, b"");
+        assert!(!out.status.success());
+        assert!(out.stdout.is_empty());
+        assert!(String::from_utf8_lossy(&out.stderr).contains(ACK));
+    }
+    for args in [&["import"][..], &["import", "--all"]] {
+        assert!(!run(&p, args, b"{\"b\": 1}").status.success());
+    }
+    assert!(!run(&p, &["get", "b"], b"").status.success());
+}
+
+#[test]
+fn wrong_password_is_refused() {
+    let p = store("wrong_password");
+    let mut cmd = microkv(&p);
+    cmd.args(["--password-env", "MICROKV_PASSWORD", "ls"])
+        .env("MICROKV_PASSWORD", "hunter2");
+    assert!(!feed(cmd, b"").status.success());
+}
+
+#[test]
+fn password_from_stdin_leaves_the_rest_for_import() {
+    let p = store("password_stdin");
+    ok(run(&p, &["put", "a", "1", "--json"], b""));
+
+    // the password line, then the dump to import: the password read mustn't eat the dump
+    let mut cmd = microkv(&p);
+    cmd.args(["--password-fd", "0", "-n", "copy", "import", ACK]);
+    let out = feed(cmd, format!("{PASSWORD}\n{{\"b\": 2}}").as_bytes());
+    ok(out);
+    assert_eq!(ok(run(&p, &["-n", "copy", "get", "b"], b"")), "2\n");
+}
+
+#[test]
+fn password_from_another_fd() {
+    let p = store("password_fd");
+    let pw = env::temp_dir().join("microkv_cli_password_fd.txt");
+    std::fs::write(&pw, format!("{PASSWORD}\r\n")).unwrap();
+
+    let out = Command::new("sh")
+        .arg("-c")
+        .arg(r#""$0" --store "$1" --password-fd 3 put k v 3<"$2""#)
+        .arg(env!("CARGO_BIN_EXE_microkv"))
+        .arg(&p)
+        .arg(&pw)
+        .env_remove("MICROKV_STORE")
+        .output()
+        .unwrap();
+    ok(out);
+    assert_eq!(ok(run(&p, &["get", "k"], b"")), "\"v\"\n");
+}
+
+#[test]
+fn password_fd_refuses_stdout_and_stderr() {
+    let p = store("password_fd_out");
+    for fd in ["1", "2"] {
+        let mut cmd = microkv(&p);
+        cmd.args(["--password-fd", fd, "ls"]);
+        let out = feed(cmd, b"");
+        assert!(!out.status.success());
+        assert!(String::from_utf8_lossy(&out.stderr).contains("file descriptor"));
+    }
+}
@@ -0,0 +1,60 @@
+#!/usr/bin/env python3
+"""Writes the pre-0.3 fixtures (v1.kv, v2.kv, flat.kv) into this directory.
+
+The layout is the one `src/migrate.rs` documents: the store handle in bincode 1.x's default
+encoding, values bincode-encoded and sealed with libsodium's crypto_secretbox_easy. Needs
+libsodium (loaded with ctypes). The nonce is fixed so the files come out the same each run.
+"""
+
+import ctypes
+import ctypes.util
+import hashlib
+import os
+import struct
+
+PASSWORD = b"fixture password"
+NONCE = bytes(range(24))
+
+sodium = ctypes.CDLL(ctypes.util.find_library("sodium") or "libsodium.so.23")
+assert sodium.sodium_init() >= 0
+
+
+def seal(plaintext):
+    key = hashlib.sha256(PASSWORD).digest()
+    out = ctypes.create_string_buffer(len(plaintext) + 16)
+    assert sodium.crypto_secretbox_easy(
+        out, plaintext, ctypes.c_ulonglong(len(plaintext)), NONCE, key
+    ) == 0
+    return out.raw
+
+
+def length(n):
+    return struct.pack("<Q", n)
+
+
+def string(s):
+    return length(len(s)) + s
+
+
+def entries(values):
+    return length(len(values)) + b"".join(string(k) + string(seal(v)) for k, v in values)
+
+
+def store(path, storage, auto_commit):
+    out = string(b"/home/user/.microkv/" + path.encode()) + storage + string(NONCE)
+    if auto_commit is not None:
+        out += bytes([auto_commit])
+    with open(os.path.join(os.path.dirname(__file__), path), "wb") as f:
+        f.write(out)
+
+
+count = struct.pack("<I", 3)
+alice = struct.pack("<Q", 1) + string(b"alice")
+bob = struct.pack("<Q", 2) + string(b"bob")
+namespaced = length(2) + string(b"") + entries([(b"count", count)]) + string(b"users") + entries(
+    [(b"alice", alice), (b"bob", bob)]
+)
+
+store("v1.kv", namespaced, None)
+store("v2.kv", namespaced, True)
+store("flat.kv", entries([(b"count", count), (b"alice", alice)]), False)
@@ -1,14 +1,22 @@
 //! Integration tests for the redesigned microkv API.
 
+// Keys are `impl AsRef<[u8]>`, but tests written against the `&str` API still pass
+// `&format!(..)`: that has to keep compiling as it is.
+#![allow(clippy::needless_borrows_for_generic_args)]
+
 use std::env;
 use std::ops::ControlFlow;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 
 use serde::{Deserialize, Serialize};
 
-use microkv::{AutoSave, Config, Credential, Error, MicroKV};
+use microkv::{
+    AutoSave, Backend, BadEntry, Cipher, Codec, Config, Credential, Error, EventKind, KdfParams,
+    MicroKV, OnConflict, Padding, SlotKind,
+};
 
 static PASSWORD: &str = "correct horse battery staple";
 
@@ -313,6 +321,124 @@ fn keys_prefix_and_for_each() {
     assert_eq!(sum, 12);
 }
 
+#[test]
+fn iterators_are_lazy_and_weakly_consistent() {
+    let db = MicroKV::in_memory(Credential::key([25; 32])).unwrap();
+    for i in 0..4u32 {
+        db.put(&format!("k{i}"), &i).unwrap();
+    }
+    db.put("other", &9u32).unwrap();
+
+    let mut it = db.iter::<u32>().unwrap();
+    assert_eq!(it.next().unwrap().unwrap(), ("k0".to_string(), 0));
+    // writing mid-iteration doesn't block: changes show, removals skip, additions don't
+    db.put("k1", &10u32).unwrap();
+    db.remove("k2").unwrap();
+    db.put("late", &5u32).unwrap();
+    let rest: Vec<(String, u32)> = it.collect::<Result<_, _>>().unwrap();
+    assert_eq!(
+        rest,
+        vec![
+            ("k1".to_string(), 10),
+            ("k3".to_string(), 3),
+            ("other".to_string(), 9)
+        ]
+    );
+
+    let keys: Vec<String> = db
+        .prefix_iter::<u32>("k")
+        .unwrap()
+        .map(|r| r.unwrap().0)
+        .collect();
+    assert_eq!(keys, vec!["k0", "k1", "k3"]);
+    assert_eq!(db.iter_keys().unwrap().count(), 5);
+
+    // an undecodable value fails its own step only
+    db.put("k0", &"text").unwrap();
+    let results: Vec<_> = db.prefix_iter::<u32>("k").unwrap().collect();
+    assert!(results[0].is_err());
+    assert_eq!(results[1].as_ref().unwrap().1, 10);
+}
+
+#[test]
+fn ordered_range_queries() {
+    for encrypt_names in [false, true] {
+        let config = Config {
+            encrypt_names,
+            ..Default::default()
+        };
+        let db = MicroKV::in_memory_with(Credential::key([26; 32]), config).unwrap();
+        let audit = db.namespace("audit");
+        for day in [17u32, 3, 21, 9] {
+            audit.put(&format!("2026-10-{day:02}"), &day).unwrap();
+        }
+        audit
+            .put_with_ttl("2026-10-30", &30u32, Duration::from_secs(0))
+            .unwrap();
+
+        fn days(it: impl Iterator<Item = microkv::Result<(String, u32)>>) -> Vec<u32> {
+            it.map(|r| r.unwrap().1).collect()
+        }
+        assert_eq!(days(audit.range(..).unwrap()), vec![3, 9, 17, 21]);
+        // plain iteration is in key order too, blind indexes or not
+        assert_eq!(days(audit.iter().unwrap()), vec![3, 9, 17, 21]);
+        assert_eq!(days(audit.iter().unwrap().rev()), vec![21, 17, 9, 3]);
+        assert_eq!(days(audit.prefix_iter("2026-10-1").unwrap()), vec![17]);
+        assert_eq!(
+            audit.keys().unwrap(),
+            ["2026-10-03", "2026-10-09", "2026-10-17", "2026-10-21"]
+        );
+        assert_eq!(
+            days(audit.range("2026-10-05".."2026-10-21").unwrap()),
+            vec![9, 17]
+        );
+        assert_eq!(days(audit.range(.."2026-10-17").unwrap().rev()), vec![9, 3]);
+        assert_eq!(days(audit.seek("2026-10-10").unwrap()), vec![17, 21]);
+        assert_eq!(audit.first::<u32>().unwrap().unwrap().0, "2026-10-03");
+        // the expired entry sorts last but isn't returned
+        assert_eq!(audit.last::<u32>().unwrap().unwrap().1, 21);
+        assert!(db.namespace("empty").first::<u32>().unwrap().is_none());
+    }
+}
+
+#[test]
+fn raw_values_and_byte_keys() {
+    let db = MicroKV::in_memory(Credential::key([27; 32])).unwrap();
+    let der = vec![0x30u8, 0x82, 0x01, 0x0a, 0x02, 0x82];
+    let fingerprint = [0xffu8, 0x00, 0x9c];
+    db.put_raw(fingerprint, &der).unwrap();
+    assert_eq!(db.get_raw(fingerprint).unwrap(), Some(der.clone()));
+    let big = vec![7u8; 70_000];
+    db.put_raw("big", &big).unwrap();
+    assert_eq!(db.get_raw("big").unwrap(), Some(big));
+
+    // binary key names round-trip through iteration; UTF-8 byte keys are just text keys
+    db.put(b"plain", &1u32).unwrap();
+    assert_eq!(db.get::<u32>("plain").unwrap(), Some(1));
+    let names: Vec<Vec<u8>> = db
+        .keys()
+        .unwrap()
+        .iter()
+        .map(|k| microkv::key_bytes(k))
+        .collect();
+    assert!(names.contains(&fingerprint.to_vec()));
+    assert!(names.contains(&b"plain".to_vec()));
+    assert!(db.contains(fingerprint).unwrap());
+
+    // typed values aren't raw
+    assert!(matches!(db.get_raw("plain"), Err(Error::Serialization(_))));
+
+    db.transaction(|tx| {
+        let cert = tx.get_raw("", fingerprint)?.unwrap();
+        tx.put_raw("certs", b"leaf", &cert)?;
+        tx.remove("", fingerprint)?;
+        Ok(())
+    })
+    .unwrap();
+    assert_eq!(db.namespace("certs").get_raw("leaf").unwrap(), Some(der));
+    assert!(db.get_raw(fingerprint).unwrap().is_none());
+}
+
 #[test]
 fn read_only_rejects_writes() {
     let path = temp("ro");
@@ -370,3 +496,1543 @@ fn concurrent_writers_share_one_store() {
         assert_eq!(db.require::<i32>(&format!("key-{ix}")).unwrap(), ix);
     }
 }
+
+#[test]
+fn wal_appends_then_compacts() {
+    let path = temp("wal");
+    let wal = std::path::PathBuf::from(format!("{}.wal", path.display()));
+    let _ = std::fs::remove_file(&wal);
+    let key = [3u8; 32];
+    let cfg = Config {
+        autosave: AutoSave::OnEveryWrite,
+        wal: true,
+        ..Default::default()
+    };
+
+    let db = MicroKV::open_with(&path, Credential::key(key), cfg.clone()).unwrap();
+    db.put("a", &1u32).unwrap();
+    let base_len = std::fs::metadata(&path).unwrap().len();
+    db.put("b", &2u32).unwrap();
+    db.namespace("ns").put("c", &"three".to_string()).unwrap();
+    assert!(db.remove("a").unwrap());
+    drop(db);
+
+    // writes went to the journal; the base file was left alone
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), base_len);
+    assert!(wal.exists());
+
+    // a torn trailing append is ignored on replay
+    let mut log = std::fs::read(&wal).unwrap();
+    log.extend_from_slice(&[0xff, 0x00, 0x00, 0x00, 0x01]);
+    std::fs::write(&wal, &log).unwrap();
+
+    // replayed even when opened without `wal`
+    let db = MicroKV::open(&path, Credential::key(key)).unwrap();
+    assert_eq!(db.get::<u32>("a").unwrap(), None);
+    assert_eq!(db.require::<u32>("b").unwrap(), 2);
+    assert_eq!(db.namespace("ns").require::<String>("c").unwrap(), "three");
+    drop(db);
+
+    let db = MicroKV::open_with(&path, Credential::key(key), cfg).unwrap();
+    db.put("d", &4u32).unwrap();
+    db.compact().unwrap();
+    assert!(!wal.exists());
+    drop(db);
+
+    let db = MicroKV::open(&path, Credential::key(key)).unwrap();
+    assert_eq!(db.keys_sorted().unwrap(), vec!["b", "d"]);
+
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn encrypted_names_hide_keys_on_disk() {
+    let path = temp("blind");
+    let key = [5u8; 32];
+    let cfg = Config {
+        autosave: AutoSave::OnEveryWrite,
+        encrypt_names: true,
+        ..Default::default()
+    };
+
+    let db = MicroKV::open_with(&path, Credential::key(key), cfg).unwrap();
+    let prod = db.namespace("prod");
+    prod.put("stripe_api_key", &"sk_live_123".to_string())
+        .unwrap();
+    prod.put("stripe_webhook", &"whsec_456".to_string())
+        .unwrap();
+    db.put("default_key", &1u32).unwrap();
+    db.rekey(Credential::key([6u8; 32])).unwrap();
+    drop(db);
+
+    let raw = std::fs::read(&path).unwrap();
+    for name in ["prod", "stripe_api_key", "default_key"] {
+        assert!(
+            !raw.windows(name.len()).any(|w| w == name.as_bytes()),
+            "{name} leaked into the file"
+        );
+    }
+
+    let db = MicroKV::open(&path, Credential::key([6u8; 32])).unwrap();
+    let prod = db.namespace("prod");
+    assert_eq!(
+        prod.require::<String>("stripe_api_key").unwrap(),
+        "sk_live_123"
+    );
+    assert_eq!(
+        prod.keys_sorted().unwrap(),
+        vec!["stripe_api_key", "stripe_webhook"]
+    );
+    assert_eq!(prod.prefix::<String>("stripe_w").unwrap().len(), 1);
+    let mut names = db.tree_names().unwrap();
+    names.sort();
+    assert_eq!(names, vec!["", "prod"]);
+    assert!(prod.remove("stripe_webhook").unwrap());
+    assert_eq!(prod.len().unwrap(), 1);
+
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn integrity_tag_catches_tampering_and_rollback() {
+    let path = temp("manifest");
+    let key = [7u8; 32];
+
+    let db = MicroKV::open_with(&path, Credential::key(key), persist_cfg()).unwrap();
+    db.put("alpha", &1u32).unwrap();
+    db.put("gamma", &2u32).unwrap();
+    let old = std::fs::read(&path).unwrap();
+    let old_generation = db.generation();
+    db.put("omega", &3u32).unwrap();
+    assert!(db.generation() > old_generation);
+    let generation = db.generation();
+    drop(db);
+
+    // renaming an entry keeps every value intact but breaks the store-wide tag
+    let current = std::fs::read(&path).unwrap();
+    let pos = current.windows(5).position(|w| w == b"gamma").unwrap();
+    let mut tampered = current.clone();
+    tampered[pos..pos + 5].copy_from_slice(b"delta");
+    std::fs::write(&path, &tampered).unwrap();
+    assert!(matches!(
+        MicroKV::open(&path, Credential::key(key)),
+        Err(Error::Integrity)
+    ));
+
+    // an older, untouched copy is authentic, but not when a newer generation is expected
+    std::fs::write(&path, &old).unwrap();
+    let cfg = Config {
+        min_generation: generation,
+        ..Default::default()
+    };
+    assert!(matches!(
+        MicroKV::open_with(&path, Credential::key(key), cfg.clone()),
+        Err(Error::Rollback { found, expected }) if found == old_generation && expected == generation
+    ));
+
+    std::fs::write(&path, &current).unwrap();
+    let db = MicroKV::open_with(&path, Credential::key(key), cfg).unwrap();
+    assert_eq!(db.keys_sorted().unwrap(), vec!["alpha", "gamma", "omega"]);
+
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn integrity_tag_keeps_up_with_every_kind_of_write() {
+    for wal in [false, true] {
+        let path = temp(&format!("manifest_tally_{wal}"));
+        let _ = std::fs::remove_file(format!("{}.wal", path.display()));
+        let key = [38u8; 32];
+        let cfg = Config {
+            wal,
+            ..persist_cfg()
+        };
+        let reopen = || MicroKV::open_with(&path, Credential::key(key), cfg.clone()).unwrap();
+
+        let db = reopen();
+        let users = db.namespace("users");
+        for i in 0..8u32 {
+            db.put(&format!("k{i}"), &i).unwrap();
+            users.put(&format!("u{i}"), &i).unwrap();
+        }
+        drop((users, db));
+
+        // each save folds in only what changed; the tag checked on open covers it all
+        let db = reopen();
+        let users = db.namespace("users");
+        db.put("k0", &100u32).unwrap();
+        db.remove("k1").unwrap();
+        let _ = db.transaction(|txn| {
+            txn.put("", "k2", &200u32)?;
+            txn.remove("users", "u0")?;
+            Err::<(), _>(Error::KeyNotFound)
+        });
+        db.transaction(|txn| {
+            txn.put("", "k3", &300u32)?;
+            txn.remove("users", "u1").map(drop)
+        })
+        .unwrap();
+        users.clear().unwrap();
+        drop((users, db));
+
+        let db = reopen();
+        db.rotate_data_key().unwrap();
+        db.put("k9", &9u32).unwrap();
+        db.rotate_step(2).unwrap();
+        drop(db);
+
+        let db = reopen();
+        assert_eq!(db.rotate_step(usize::MAX).unwrap(), 0);
+        db.put("k4", &400u32).unwrap();
+        drop(db);
+
+        let db = reopen();
+        assert_eq!(
+            db.keys_sorted().unwrap(),
+            vec!["k0", "k2", "k3", "k4", "k5", "k6", "k7", "k9"]
+        );
+        assert_eq!(db.get::<u32>("k3").unwrap(), Some(300));
+        assert!(db.namespace("users").is_empty().unwrap());
+        drop(db);
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(format!("{}.wal", path.display()));
+    }
+}
+
+#[test]
+fn key_slots_open_independently() {
+    let path = temp("slots");
+    let (operator, ci) = ([8u8; 32], [9u8; 32]);
+    let cfg = Config {
+        autosave: AutoSave::OnEveryWrite,
+        kdf: KdfParams::scrypt(10, 8, 1),
+        ..Default::default()
+    };
+
+    let db = MicroKV::open_with(&path, Credential::key(operator), cfg).unwrap();
+    db.put("k", &"v".to_string()).unwrap();
+    let recovery = db.add_credential(Credential::password("recovery")).unwrap();
+    let ci_slot = db.add_credential(Credential::key(ci)).unwrap();
+    let slots = db.list_slots().unwrap();
+    assert_eq!(slots.len(), 3);
+    assert!(slots[0].active && !slots[1].active);
+    assert_eq!(slots[1].kind, SlotKind::Password);
+    drop(db);
+
+    for cred in [
+        Credential::key(operator),
+        Credential::password("recovery"),
+        Credential::key(ci),
+    ] {
+        let db = MicroKV::open(&path, cred).unwrap();
+        assert_eq!(db.require::<String>("k").unwrap(), "v");
+    }
+
+    // rekey only replaces the slot it was opened through
+    let db = MicroKV::open_with(&path, Credential::key(ci), persist_cfg()).unwrap();
+    db.rekey(Credential::key([10u8; 32])).unwrap();
+    assert!(db
+        .list_slots()
+        .unwrap()
+        .iter()
+        .any(|s| s.id == ci_slot && s.active));
+    db.remove_credential(recovery).unwrap();
+    assert!(matches!(
+        db.remove_credential(recovery),
+        Err(Error::NoSuchSlot(_))
+    ));
+    drop(db);
+
+    assert!(matches!(
+        MicroKV::open(&path, Credential::key(ci)),
+        Err(Error::WrongPassword)
+    ));
+    assert!(matches!(
+        MicroKV::open(&path, Credential::password("recovery")),
+        Err(Error::WrongPassword)
+    ));
+    let db = MicroKV::open_with(&path, Credential::key([10u8; 32]), persist_cfg()).unwrap();
+    assert_eq!(db.require::<String>("k").unwrap(), "v");
+    db.remove_credential(0).unwrap();
+    assert!(matches!(
+        db.remove_credential(ci_slot),
+        Err(Error::LastSlot)
+    ));
+
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn rotate_data_key_reencrypts_entries() {
+    let path = temp("rotate");
+    let key = [11u8; 32];
+    let cfg = Config {
+        autosave: AutoSave::OnEveryWrite,
+        encrypt_names: true,
+        wal: true,
+        ..Default::default()
+    };
+
+    let db = MicroKV::open_with(&path, Credential::key(key), cfg.clone()).unwrap();
+    db.put("a", &1u32).unwrap();
+    db.namespace("ns").put("b", &"two".to_string()).unwrap();
+    db.rekey(Credential::key([12u8; 32])).unwrap();
+    db.rotate_data_key().unwrap();
+    db.put("c", &3u32).unwrap();
+    drop(db);
+
+    let db = MicroKV::open_with(&path, Credential::key([12u8; 32]), cfg).unwrap();
+    assert_eq!(db.keys_sorted().unwrap(), vec!["a", "c"]);
+    assert_eq!(db.namespace("ns").require::<String>("b").unwrap(), "two");
+
+    let _ = std::fs::remove_file(&path);
+    let _ = std::fs::remove_file(format!("{}.wal", path.display()));
+}
+
+/// `current` with the key slots (and the rest of the header they open) of `old`, as
+/// someone holding an old copy of the file and a revoked credential could build it.
+fn graft_old_header(old: &[u8], current: &[u8]) -> Vec<u8> {
+    let decode = |mut bytes: &[u8]| match rmpv::decode::read_value(&mut bytes).unwrap() {
+        rmpv::Value::Array(fields) => fields,
+        other => panic!("store file isn't an array: {other:?}"),
+    };
+    let (old, mut grafted) = (decode(old), decode(current));
+    // kdf, salt, verifier, features, slots
+    for field in [2, 3, 4, 7, 10] {
+        grafted[field] = old[field].clone();
+    }
+    let mut out = Vec::new();
+    rmpv::encode::write_value(&mut out, &rmpv::Value::Array(grafted)).unwrap();
+    out
+}
+
+#[test]
+fn rotating_the_master_key_locks_out_removed_credentials() {
+    let path = temp("rotate_master");
+    let owner = || Credential::key([34; 32]);
+    let cfg = Config {
+        autosave: AutoSave::OnEveryWrite,
+        kdf: KdfParams::scrypt(10, 8, 1),
+        ..Default::default()
+    };
+    let db = MicroKV::open_with(&path, owner(), cfg).unwrap();
+    let leaked = db.add_credential(Credential::password("leaked")).unwrap();
+    db.put("old", &1u8).unwrap();
+    let old_copy = std::fs::read(&path).unwrap();
+    db.remove_credential(leaked).unwrap();
+
+    // a new data key alone is wrapped under the master key the old copy gives away
+    db.rotate_data_key().unwrap();
+    db.put("new", &2u8).unwrap();
+    let graft = graft_old_header(&old_copy, &std::fs::read(&path).unwrap());
+    let stolen = MicroKV::from_bytes(&graft, Credential::password("leaked"), Config::default());
+    assert_eq!(stolen.unwrap().get::<u8>("new").unwrap(), Some(2));
+
+    assert!(matches!(
+        db.rotate_master_key(&[Credential::password("leaked")]),
+        Err(Error::MissingCredential(0))
+    ));
+    assert_eq!(db.rotate_master_key(&[owner()]).unwrap(), 2);
+    db.put("newer", &3u8).unwrap();
+    assert_eq!(db.rotate_step(usize::MAX).unwrap(), 0);
+    let graft = graft_old_header(&old_copy, &std::fs::read(&path).unwrap());
+    assert!(
+        MicroKV::from_bytes(&graft, Credential::password("leaked"), Config::default()).is_err()
+    );
+    drop(db);
+
+    let db = MicroKV::open(&path, owner()).unwrap();
+    for (key, value) in [("old", 1u8), ("new", 2), ("newer", 3)] {
+        assert_eq!(db.require::<u8>(key).unwrap(), value);
+    }
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn data_key_rotation_migrates_in_batches() {
+    let path = temp("epochs");
+    let key = [13u8; 32];
+
+    let db = MicroKV::open_with(&path, Credential::key(key), persist_cfg()).unwrap();
+    for ix in 0..5u32 {
+        db.put(format!("k{ix}"), &ix).unwrap();
+    }
+    assert_eq!(db.begin_data_key_rotation().unwrap(), 5);
+    db.put("k0", &10u32).unwrap(); // rewritten under the new key
+    assert_eq!(db.rotate_step(2).unwrap(), 2);
+    drop(db);
+
+    // old and new epochs are both readable mid-rotation
+    let db = MicroKV::open_with(&path, Credential::key(key), persist_cfg()).unwrap();
+    assert_eq!(db.require::<u32>("k0").unwrap(), 10);
+    for ix in 1..5u32 {
+        assert_eq!(db.require::<u32>(&format!("k{ix}")).unwrap(), ix);
+    }
+    assert_eq!(db.rotate_step(100).unwrap(), 0);
+    assert_eq!(db.rotate_step(100).unwrap(), 0);
+    drop(db);
+
+    let db = MicroKV::open(&path, Credential::key(key)).unwrap();
+    assert_eq!(db.require::<u32>("k4").unwrap(), 4);
+
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn kdf_upgrades_rewrap_slots() {
+    let path = temp("kdf_upgrade");
+    let cheap = || KdfParams::scrypt(10, 8, 1);
+    let has_scrypt = |log_n: u8| {
+        let raw = std::fs::read(&path).unwrap();
+        raw.windows(4).any(|w| w == [0x93, log_n, 8, 1])
+    };
+    let cfg = Config {
+        autosave: AutoSave::OnEveryWrite,
+        kdf: cheap(),
+        ..Default::default()
+    };
+
+    let db = MicroKV::open_with(&path, Credential::password("pw"), cfg).unwrap();
+    db.put("k", &1u32).unwrap();
+    assert!(matches!(
+        db.upgrade_kdf(Credential::password("other"), KdfParams::scrypt(11, 8, 1)),
+        Err(Error::WrongPassword)
+    ));
+    db.upgrade_kdf(Credential::password("pw"), KdfParams::scrypt(11, 8, 1))
+        .unwrap();
+    assert!(has_scrypt(11) && !has_scrypt(10));
+    drop(db);
+
+    let db = MicroKV::open_with(&path, Credential::password("pw"), persist_cfg()).unwrap();
+    db.rekey_with(Credential::password("pw2"), KdfParams::scrypt(12, 8, 1))
+        .unwrap();
+    db.change_password_with_kdf("pw2", "pw3", cheap()).unwrap();
+    assert!(has_scrypt(10) && !has_scrypt(12));
+    drop(db);
+
+    assert!(MicroKV::open(&path, Credential::password("pw2")).is_err());
+    let db = MicroKV::open(&path, Credential::password("pw3")).unwrap();
+    assert_eq!(db.require::<u32>("k").unwrap(), 1);
+
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn alternative_ciphers_round_trip() {
+    let mut ciphers = vec![Cipher::XChaCha20Poly1305];
+    if cfg!(feature = "aes-gcm-siv") {
+        ciphers.push(Cipher::Aes256GcmSiv);
+    } else {
+        let cfg = Config {
+            cipher: Cipher::Aes256GcmSiv,
+            ..Default::default()
+        };
+        assert!(MicroKV::in_memory_with(Credential::key([14u8; 32]), cfg).is_err());
+    }
+
+    for (ix, cipher) in ciphers.into_iter().enumerate() {
+        let path = temp(&format!("cipher_{ix}"));
+        let key = [14u8; 32];
+        let cfg = Config {
+            autosave: AutoSave::OnEveryWrite,
+            cipher,
+            wal: true,
+            ..Default::default()
+        };
+
+        let db = MicroKV::open_with(&path, Credential::key(key), cfg).unwrap();
+        db.put("a", &1u32).unwrap();
+        db.namespace("ns").put("b", &"two".to_string()).unwrap();
+        db.rotate_data_key().unwrap();
+        db.put("c", &3u32).unwrap();
+        drop(db);
+
+        // the header decides; `Config::cipher` is ignored for existing stores
+        let db = MicroKV::open(&path, Credential::key(key)).unwrap();
+        assert_eq!(db.keys_sorted().unwrap(), vec!["a", "c"]);
+        assert_eq!(db.namespace("ns").require::<String>("b").unwrap(), "two");
+
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(format!("{}.wal", path.display()));
+    }
+}
+
+#[test]
+fn padding_hides_value_lengths() {
+    let key = [15u8; 32];
+    let stored_len = |name: &str, padding: Padding, value: String| {
+        let path = temp(name);
+        let cfg = Config {
+            autosave: AutoSave::OnEveryWrite,
+            padding,
+            encrypt_names: true,
+            ..Default::default()
+        };
+        let db = MicroKV::open_with(&path, Credential::key(key), cfg).unwrap();
+        db.namespace("pins")
+            .put_with_ttl("k", &value, Duration::from_secs(60))
+            .unwrap();
+        drop(db);
+        let len = std::fs::metadata(&path).unwrap().len() as i64;
+        let db = MicroKV::open(&path, Credential::key(key)).unwrap();
+        assert_eq!(db.namespace("pins").require::<String>("k").unwrap(), value);
+        let _ = std::fs::remove_file(&path);
+        len
+    };
+
+    // ciphertext bytes are msgpack ints of varying width, so sizes are only close
+    let (short, long) = ("1234".to_string(), "x".repeat(3000));
+    let unpadded = stored_len("pad_none", Padding::None, long.clone())
+        - stored_len("pad_none", Padding::None, short.clone());
+    assert!(unpadded > 3000);
+    for (padding, short) in [
+        (Padding::PowerOfTwo, "y".repeat(2100)),
+        (Padding::Block(4096), short.clone()),
+        (
+            Padding::Namespaces([("pins".to_string(), 4096)].into()),
+            short,
+        ),
+    ] {
+        let padded =
+            stored_len("pad", padding.clone(), long.clone()) - stored_len("pad", padding, short);
+        assert!(padded.abs() < 500, "{padded}");
+    }
+}
+
+#[test]
+fn from_bytes_reopens_exports() {
+    let key = [16u8; 32];
+    let db = MicroKV::in_memory(Credential::key(key)).unwrap();
+    db.put("a", &1u32).unwrap();
+    db.namespace("ns").put("b", &"two".to_string()).unwrap();
+    let bytes = db.export().unwrap();
+
+    let copy = MicroKV::from_bytes(&bytes, Credential::key(key), Config::default()).unwrap();
+    assert_eq!(copy.require::<u32>("a").unwrap(), 1);
+    assert_eq!(copy.namespace("ns").require::<String>("b").unwrap(), "two");
+    assert!(matches!(copy.save(), Err(Error::NoPath)));
+
+    let copy = MicroKV::from_reader(&bytes[..], Credential::key(key), Config::default()).unwrap();
+    assert_eq!(copy.keys().unwrap(), vec!["a"]);
+
+    assert!(matches!(
+        MicroKV::from_bytes(&bytes, Credential::key([0u8; 32]), Config::default()),
+        Err(Error::WrongPassword)
+    ));
+    assert!(matches!(
+        MicroKV::from_bytes(b"not a store", Credential::key(key), Config::default()),
+        Err(Error::CorruptStore(_))
+    ));
+    let mut tampered = bytes.clone();
+    let last = tampered.len() - 1;
+    tampered[last] ^= 1;
+    assert!(MicroKV::from_bytes(&tampered, Credential::key(key), Config::default()).is_err());
+}
+
+/// A backend test double: shared bytes and journal, and a switch that fails every write.
+#[derive(Debug, Clone, Default)]
+struct MemBackend {
+    bytes: Arc<Mutex<Option<Vec<u8>>>>,
+    journal: Arc<Mutex<Option<Vec<u8>>>>,
+    fail: Arc<AtomicBool>,
+}
+
+impl MemBackend {
+    fn check(&self) -> microkv::Result<()> {
+        if self.fail.load(Ordering::SeqCst) {
+            return Err(Error::Io(std::io::Error::other("injected fault")));
+        }
+        Ok(())
+    }
+}
+
+impl Backend for MemBackend {
+    fn exists(&self) -> microkv::Result<bool> {
+        Ok(self.bytes.lock().unwrap().is_some())
+    }
+
+    fn load(&self) -> microkv::Result<Vec<u8>> {
+        Ok(self.bytes.lock().unwrap().clone().unwrap())
+    }
+
+    fn store(&self, bytes: &[u8]) -> microkv::Result<()> {
+        self.check()?;
+        *self.bytes.lock().unwrap() = Some(bytes.to_vec());
+        Ok(())
+    }
+
+    fn remove(&self) -> microkv::Result<()> {
+        *self.bytes.lock().unwrap() = None;
+        *self.journal.lock().unwrap() = None;
+        Ok(())
+    }
+
+    fn supports_journal(&self) -> bool {
+        true
+    }
+
+    fn load_journal(&self) -> microkv::Result<Option<Vec<u8>>> {
+        Ok(self.journal.lock().unwrap().clone())
+    }
+
+    fn append_journal(&self, offset: u64, bytes: &[u8]) -> microkv::Result<()> {
+        self.check()?;
+        let mut journal = self.journal.lock().unwrap();
+        let journal = journal.get_or_insert_with(Vec::new);
+        journal.truncate(offset as usize);
+        journal.extend_from_slice(bytes);
+        Ok(())
+    }
+
+    fn remove_journal(&self) -> microkv::Result<()> {
+        *self.journal.lock().unwrap() = None;
+        Ok(())
+    }
+}
+
+#[test]
+fn custom_backend_persists_and_survives_faults() {
+    let key = [17u8; 32];
+    let backend = MemBackend::default();
+    let cfg = || Config {
+        autosave: AutoSave::OnEveryWrite,
+        wal: true,
+        ..Default::default()
+    };
+    assert!(matches!(
+        MicroKV::open_existing_backend(backend.clone(), Credential::key(key), cfg()),
+        Err(Error::Io(_))
+    ));
+
+    let db = MicroKV::create_new_backend(backend.clone(), Credential::key(key), cfg()).unwrap();
+    db.put("a", &1u32).unwrap();
+    db.put("b", &2u32).unwrap();
+    assert!(backend.journal.lock().unwrap().is_some());
+
+    backend.fail.store(true, Ordering::SeqCst);
+    assert!(db.put("c", &3u32).is_err());
+    assert!(db.compact().is_err());
+    drop(db);
+    backend.fail.store(false, Ordering::SeqCst);
+
+    // the failed writes never reached the backend
+    let db = MicroKV::open_backend(backend.clone(), Credential::key(key), cfg()).unwrap();
+    assert_eq!(db.keys_sorted().unwrap(), vec!["a", "b"]);
+    db.compact().unwrap();
+    assert!(backend.journal.lock().unwrap().is_none());
+    assert!(matches!(
+        MicroKV::create_new_backend(backend.clone(), Credential::key(key), cfg()),
+        Err(Error::AlreadyExists)
+    ));
+
+    db.destroy().unwrap();
+    assert!(!backend.exists().unwrap());
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn plaintext_dump_round_trips() {
+    use microkv::PlaintextAck;
+    const ACK: PlaintextAck = PlaintextAck::I_UNDERSTAND_THIS_IS_UNENCRYPTED;
+
+    let cfg = Config {
+        encrypt_names: true,
+        ..Default::default()
+    };
+    let src = MicroKV::in_memory_with(Credential::key([18u8; 32]), cfg).unwrap();
+    let alice = User {
+        id: 1,
+        name: "alice".into(),
+    };
+    src.put("count", &3u32).unwrap();
+    src.namespace("users").put("alice", &alice).unwrap();
+    src.namespace("otp")
+        .put_with_ttl("code", &"123456".to_string(), Duration::from_secs(600))
+        .unwrap();
+
+    let mut dump = Vec::new();
+    src.export_plaintext(&mut dump, ACK).unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&dump).unwrap();
+    assert_eq!(json["namespaces"][""]["count"]["value"], 3);
+    assert!(json["namespaces"]["otp"]["code"]["expires_at"].is_u64());
+    assert!(json["namespaces"]["users"]["alice"]
+        .get("expires_at")
+        .is_none());
+
+    let dst = MicroKV::in_memory(Credential::key([19u8; 32])).unwrap();
+    assert_eq!(dst.import_plaintext(&dump[..], ACK).unwrap(), 3);
+    assert_eq!(dst.require::<u32>("count").unwrap(), 3);
+    assert_eq!(
+        dst.namespace("users").require::<User>("alice").unwrap(),
+        alice
+    );
+    assert_eq!(
+        dst.namespace("otp").require::<String>("code").unwrap(),
+        "123456"
+    );
+
+    assert!(matches!(
+        dst.import_plaintext(&b"{\"namespaces\": {}}"[..], ACK),
+        Err(Error::Serialization(_))
+    ));
+    let unknown = br#"{"format": "microkv-plaintext", "version": 2, "namespaces": {}}"#;
+    assert!(matches!(
+        dst.import_plaintext(&unknown[..], ACK),
+        Err(Error::Serialization(_))
+    ));
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn plaintext_dump_keeps_binary_values() {
+    use std::collections::BTreeMap;
+
+    use microkv::PlaintextAck;
+    const ACK: PlaintextAck = PlaintextAck::I_UNDERSTAND_THIS_IS_UNENCRYPTED;
+
+    let mut config = Config::default();
+    if cfg!(feature = "postcard") {
+        config
+            .namespace_codecs
+            .insert("compact".into(), Codec::Postcard);
+    }
+    let src = MicroKV::in_memory_with(Credential::key([35; 32]), config.clone()).unwrap();
+    src.put_raw("blob", &[0, 159, 146, 150, 255]).unwrap();
+    let by_id: BTreeMap<u32, String> = [(1, "one".to_string())].into();
+    src.put("by_id", &by_id).unwrap();
+    src.put("name", &"alice").unwrap();
+    if cfg!(feature = "postcard") {
+        let compact = src.namespace("compact");
+        compact.put("pair", &(7u16, "seven".to_string())).unwrap();
+        compact.put_raw("raw", b"bytes").unwrap();
+    }
+
+    let mut dump = Vec::new();
+    src.export_plaintext(&mut dump, ACK).unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&dump).unwrap();
+    let root = &json["namespaces"][""];
+    assert_eq!(root["blob"]["bytes"], "AJ+Slv8=");
+    assert_eq!(root["by_id"]["encoded"]["codec"], "MessagePack");
+    assert_eq!(root["name"]["value"], "alice");
+
+    let dst = MicroKV::in_memory_with(Credential::key([36; 32]), config).unwrap();
+    let expected = if cfg!(feature = "postcard") { 5 } else { 3 };
+    assert_eq!(dst.import_plaintext(&dump[..], ACK).unwrap(), expected);
+    assert_eq!(
+        dst.get_raw("blob").unwrap().unwrap(),
+        [0, 159, 146, 150, 255]
+    );
+    assert_eq!(
+        dst.require::<BTreeMap<u32, String>>("by_id").unwrap(),
+        by_id
+    );
+    if cfg!(feature = "postcard") {
+        let compact = dst.namespace("compact");
+        let pair: (u16, String) = compact.require("pair").unwrap();
+        assert_eq!(pair, (7, "seven".to_string()));
+        assert_eq!(compact.get_raw("raw").unwrap().unwrap(), b"bytes");
+
+        // encoded values only load into a namespace with the same codec, and a refused
+        // dump writes nothing
+        let msgpack = MicroKV::in_memory(Credential::key([36; 32])).unwrap();
+        assert!(matches!(
+            msgpack.import_plaintext(&dump[..], ACK),
+            Err(Error::Serialization(_))
+        ));
+        assert!(msgpack.tree_names().unwrap().is_empty());
+    }
+}
+
+#[test]
+fn backups_are_sealed_under_their_own_credential() {
+    let (key, backup_key) = ([20u8; 32], [21u8; 32]);
+    let path = temp("backup_bundle");
+    let cfg = Config {
+        encrypt_names: true,
+        ..Default::default()
+    };
+    let db = MicroKV::in_memory_with(Credential::key(key), cfg).unwrap();
+    db.put("a", &1u32).unwrap();
+    db.namespace("users")
+        .put("alice", &"admin".to_string())
+        .unwrap();
+    db.namespace("otp")
+        .put_with_ttl("code", &"123456".to_string(), Duration::from_secs(600))
+        .unwrap();
+
+    db.backup_namespaces_to(&path, Credential::key(backup_key), &["", "otp"])
+        .unwrap();
+    assert!(matches!(
+        MicroKV::open_existing(&path, Credential::key(key)),
+        Err(Error::WrongPassword)
+    ));
+    let bundle = MicroKV::open_existing(&path, Credential::key(backup_key)).unwrap();
+    let mut names = bundle.tree_names().unwrap();
+    names.sort();
+    assert_eq!(names, vec!["", "otp"]);
+    drop(bundle);
+
+    let other = MicroKV::in_memory(Credential::key([22u8; 32])).unwrap();
+    other.put("a", &9u32).unwrap();
+    other.put("b", &2u32).unwrap();
+    let restore = |policy| other.restore_from(&path, Credential::key(backup_key), policy);
+    let otp = other.namespace("otp").watch_prefix("").unwrap();
+    assert!(matches!(
+        restore(OnConflict::Fail),
+        Err(Error::Conflict { key, .. }) if key == "a"
+    ));
+    assert!(!other.namespace("otp").contains("code").unwrap());
+    assert!(otp.try_recv().is_err());
+
+    assert_eq!(restore(OnConflict::Skip).unwrap(), 1);
+    assert_eq!(otp.try_recv().unwrap().key, "code");
+    assert_eq!(other.require::<u32>("a").unwrap(), 9);
+    assert_eq!(
+        other.namespace("otp").require::<String>("code").unwrap(),
+        "123456"
+    );
+    assert_eq!(restore(OnConflict::Overwrite).unwrap(), 2);
+    assert_eq!(other.require::<u32>("a").unwrap(), 1);
+    assert_eq!(other.require::<u32>("b").unwrap(), 2);
+
+    let _ = std::fs::remove_file(&path);
+}
+
+/// A copy of a store in an older format (see `tests/fixtures/README.md`), and the
+/// offset of its format version.
+fn fixture(name: &str) -> (std::path::PathBuf, usize) {
+    let raw = std::fs::read(format!(
+        "{}/tests/fixtures/{name}.kv",
+        env!("CARGO_MANIFEST_DIR")
+    ))
+    .unwrap();
+    let path = temp(&format!("fixture_{name}"));
+    std::fs::write(&path, &raw).unwrap();
+    // the version follows the magic
+    let at = raw.windows(8).position(|w| w == b"\xa7microkv").unwrap() + 8;
+    (path, at)
+}
+
+#[test]
+fn older_formats_open_and_upgrade() {
+    let cred = || Credential::password("fixture password");
+    let check = |db: &MicroKV| {
+        assert_eq!(db.require::<u32>("count").unwrap(), 3);
+        let users = db.namespace("users");
+        assert_eq!(users.keys_sorted().unwrap(), vec!["alice", "bob"]);
+        let alice: (u64, String) = users.require("alice").unwrap();
+        assert_eq!(alice, (1, "alice".to_string()));
+        let otp = db.namespace("otp");
+        assert_eq!(otp.require::<String>("code").unwrap(), "123456");
+        assert!(otp.ttl("code").unwrap().is_some());
+    };
+
+    for (name, version) in [("v3", 3), ("v4", 4), ("v4_encrypted_names", 4)] {
+        let (path, at) = fixture(name);
+        let original = std::fs::read(&path).unwrap();
+        assert_eq!(original[at], version);
+
+        // read-only opens leave the file alone
+        let ro = Config {
+            read_only: true,
+            ..Default::default()
+        };
+        check(&MicroKV::open_with(&path, cred(), ro).unwrap());
+        assert_eq!(std::fs::read(&path).unwrap(), original);
+
+        // so do writable opens that may not upgrade
+        let keep = Config {
+            allow_upgrade: false,
+            ..Default::default()
+        };
+        assert!(matches!(
+            MicroKV::open_with(&path, cred(), keep),
+            Err(Error::UnsupportedStoreVersion { found, .. }) if found == version
+        ));
+        assert_eq!(std::fs::read(&path).unwrap(), original);
+
+        // by default, a writable open upgrades
+        let db = MicroKV::open(&path, cred()).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap()[at], 6);
+        check(&db);
+        db.put("count", &4u32).unwrap();
+        db.save().unwrap();
+        drop(db);
+        let db = MicroKV::open(&path, cred()).unwrap();
+        assert_eq!(db.require::<u32>("count").unwrap(), 4);
+        assert!(matches!(
+            MicroKV::open(&path, Credential::password("wrong")),
+            Err(Error::WrongPassword)
+        ));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    // nor is a current header claiming a version from before 0.3, which had none
+    let (path, at) = fixture("v3");
+    let mut raw = std::fs::read(&path).unwrap();
+    raw[at] = 2;
+    std::fs::write(&path, &raw).unwrap();
+    assert!(matches!(
+        MicroKV::open(&path, cred()),
+        Err(Error::UnsupportedStoreVersion { found: 2, .. })
+    ));
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn pre_0_3_stores_convert() {
+    let cred = || Credential::password("fixture password");
+    // the converted store gets a new key slot, derived under this
+    let cheap = Config {
+        kdf: KdfParams::scrypt(10, 8, 1),
+        ..Default::default()
+    };
+    for (name, version) in [("v1", 1), ("v2", 2), ("flat", 2)] {
+        let original = std::fs::read(format!(
+            "{}/tests/fixtures/{name}.kv",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let path = temp(&format!("fixture_{name}"));
+        std::fs::write(&path, &original).unwrap();
+        let users = if name == "flat" { "" } else { "users" };
+        let check = |db: &MicroKV| {
+            assert_eq!(db.require::<u32>("count").unwrap(), 3);
+            let alice: (u64, String) = db.namespace(users).require("alice").unwrap();
+            assert_eq!(alice, (1, "alice".to_string()));
+        };
+
+        assert!(matches!(
+            MicroKV::open_with(&path, Credential::password("wrong"), cheap.clone()),
+            Err(Error::WrongPassword)
+        ));
+        let keep = Config {
+            allow_upgrade: false,
+            ..cheap.clone()
+        };
+        assert!(matches!(
+            MicroKV::open_with(&path, cred(), keep),
+            Err(Error::UnsupportedStoreVersion { found, .. }) if found == version
+        ));
+        let ro = Config {
+            read_only: true,
+            ..cheap.clone()
+        };
+        let result = MicroKV::open_with(&path, cred(), ro);
+        assert_eq!(std::fs::read(&path).unwrap(), original);
+        if !cfg!(feature = "bincode") {
+            // the values are bincode, which the store can't decode without it
+            assert!(matches!(result, Err(Error::CorruptStore(_))));
+            let _ = std::fs::remove_file(&path);
+            continue;
+        }
+        check(&result.unwrap());
+
+        let db = MicroKV::open_with(&path, cred(), cheap.clone()).unwrap();
+        assert!(std::fs::read(&path)
+            .unwrap()
+            .windows(8)
+            .any(|w| w == b"\xa7microkv"));
+        check(&db);
+        if name != "flat" {
+            let bob: (u64, String) = db.namespace("users").require("bob").unwrap();
+            assert_eq!(bob, (2, "bob".to_string()));
+        }
+        db.put("count", &4u32).unwrap();
+        db.save().unwrap();
+        drop(db);
+        let db = MicroKV::open(&path, cred()).unwrap();
+        assert_eq!(db.require::<u32>("count").unwrap(), 4);
+        drop(db);
+        let _ = std::fs::remove_file(&path);
+    }
+}
+
+#[test]
+fn stores_with_a_data_key_are_written_as_v6() {
+    // a v5 reader would open the entries with the master key
+    let path = temp("v6_data_key");
+    let db = MicroKV::open(&path, Credential::key([39u8; 32])).unwrap();
+    db.save().unwrap();
+    let raw = std::fs::read(&path).unwrap();
+    let at = raw.windows(8).position(|w| w == b"\xa7microkv").unwrap() + 8;
+    assert_eq!(raw[at], 6);
+    drop(db);
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn upgraded_stores_stop_opening_with_a_changed_password() {
+    let (path, _) = fixture("v3");
+    let original = std::fs::read(&path).unwrap();
+    let db = MicroKV::open(&path, Credential::password("fixture password")).unwrap();
+    db.change_password("fixture password", "new password")
+        .unwrap();
+    db.save().unwrap();
+    drop(db);
+
+    assert!(matches!(
+        MicroKV::open(&path, Credential::password("fixture password")),
+        Err(Error::WrongPassword)
+    ));
+    // nor are the entries still sealed under the key the old password derives: put the v3
+    // header back in front of them
+    let decode = |mut bytes: &[u8]| match rmpv::decode::read_value(&mut bytes).unwrap() {
+        rmpv::Value::Array(fields) => fields,
+        other => panic!("store file isn't an array: {other:?}"),
+    };
+    let (old, mut fields) = (decode(&original), decode(&std::fs::read(&path).unwrap()));
+    // kdf, salt, verifier
+    for field in [2, 3, 4] {
+        fields[field] = old[field].clone();
+    }
+    // features, slots, retired keys
+    for field in [7, 10, 12] {
+        fields[field] = rmpv::Value::Array(Vec::new());
+    }
+    fields[11] = rmpv::Value::Nil; // data key
+    let mut graft = Vec::new();
+    rmpv::encode::write_value(&mut graft, &rmpv::Value::Array(fields)).unwrap();
+    let stolen = MicroKV::from_bytes(
+        &graft,
+        Credential::password("fixture password"),
+        Config::default(),
+    )
+    .unwrap();
+    assert!(stolen.get::<u32>("count").is_err());
+
+    let db = MicroKV::open(&path, Credential::password("new password")).unwrap();
+    assert_eq!(db.require::<u32>("count").unwrap(), 3);
+    drop(db);
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn verify_reports_and_salvage_drops_bad_entries() {
+    let key = [24u8; 32];
+    let path = temp("salvage");
+    let db = MicroKV::open(&path, Credential::key(key)).unwrap();
+    for k in ["aaa", "bbb", "ccc"] {
+        db.put(k, &k.to_string()).unwrap();
+    }
+    db.save().unwrap();
+    drop(db);
+
+    // each entry follows its key as [nonce, data], both arrays of msgpack uints; flip the
+    // low bit of one of those (a fixint, or the payload of a `0xcc` u8)
+    let entry_at = |raw: &[u8], k: &str| {
+        let name = [&[0xa3][..], k.as_bytes()].concat();
+        raw.windows(4).position(|w| w == name).unwrap() + 4
+    };
+    let flip = |raw: &mut [u8], at: usize| match raw[at] {
+        0xcc => raw[at + 1] ^= 1,
+        _ => raw[at] ^= 1,
+    };
+    let mut raw = std::fs::read(&path).unwrap();
+    let mut at = entry_at(&raw, "ccc") + 2; // past [fixarray, fixarray(12)]
+    for _ in 0..12 {
+        at += if raw[at] == 0xcc { 2 } else { 1 };
+    }
+    assert_eq!(raw[at], 0xdc); // array16 of ciphertext bytes
+    flip(&mut raw, at + 3);
+    std::fs::write(&path, &raw).unwrap();
+
+    let db = MicroKV::open(&path, Credential::key(key)).unwrap();
+    assert!(db.keys().is_err());
+    let report = db.verify().unwrap();
+    assert_eq!(report.checked, 3);
+    assert_eq!(
+        report.bad,
+        vec![BadEntry {
+            namespace: String::new(),
+            key: "ccc".into()
+        }]
+    );
+    assert!(db.salvage_report().is_none());
+    drop(db);
+
+    let at = entry_at(&raw, "bbb");
+    flip(&mut raw, at + 2); // the nonce, which the integrity tag covers
+    std::fs::write(&path, &raw).unwrap();
+    assert!(matches!(
+        MicroKV::open(&path, Credential::key(key)),
+        Err(Error::Integrity)
+    ));
+
+    let cfg = Config {
+        salvage: true,
+        ..Default::default()
+    };
+    let db = MicroKV::open_with(&path, Credential::key(key), cfg).unwrap();
+    let report = db.salvage_report().unwrap();
+    assert!(report.integrity_failed && !report.is_clean());
+    let bad: Vec<_> = report.bad.iter().map(|b| b.key.as_str()).collect();
+    assert_eq!(bad, vec!["bbb", "ccc"]);
+    assert_eq!(db.keys().unwrap(), vec!["aaa"]);
+    assert!(db.verify().unwrap().is_clean());
+    db.save().unwrap();
+    drop(db);
+
+    let db = MicroKV::open(&path, Credential::key(key)).unwrap();
+    assert_eq!(db.require::<String>("aaa").unwrap(), "aaa");
+    assert_eq!(db.keys().unwrap(), vec!["aaa"]);
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn salvage_reads_what_it_can_of_an_undecodable_file() {
+    let key = [37u8; 32];
+    let path = temp("salvage_undecodable");
+    let db = MicroKV::open(&path, Credential::key(key)).unwrap();
+    for k in ["aaa", "bbb", "ccc"] {
+        db.put(k, &k.to_string()).unwrap();
+    }
+    db.namespace("later").put("x", &1u32).unwrap();
+    db.save().unwrap();
+    drop(db);
+
+    // break the msgpack itself: the entry after "bbb" starts with a byte msgpack never uses
+    let mut raw = std::fs::read(&path).unwrap();
+    let at = raw.windows(4).position(|w| w == b"\xa3bbb").unwrap();
+    raw[at + 4] = 0xc1;
+    std::fs::write(&path, &raw).unwrap();
+    assert!(matches!(
+        MicroKV::open(&path, Credential::key(key)),
+        Err(Error::CorruptStore(_))
+    ));
+
+    let salvage = || Config {
+        salvage: true,
+        ..Default::default()
+    };
+    let db = MicroKV::open_with(&path, Credential::key(key), salvage()).unwrap();
+    let report = db.salvage_report().unwrap();
+    let lost = report.lost.as_ref().unwrap();
+    assert_eq!(lost.offset, at);
+    assert_eq!(lost.namespace.as_deref(), Some(""));
+    assert!(report.integrity_failed && !report.is_clean());
+    assert_eq!(db.keys().unwrap(), vec!["aaa"]);
+    assert!(db.tree_names().unwrap().iter().all(|ns| ns != "later"));
+    db.save().unwrap();
+    drop(db);
+
+    let db = MicroKV::open(&path, Credential::key(key)).unwrap();
+    assert_eq!(db.require::<String>("aaa").unwrap(), "aaa");
+    drop(db);
+    let _ = std::fs::remove_file(&path);
+
+    // a truncated v3 store (nothing follows its entries) keeps the entries before the cut
+    let (path, _) = fixture("v3");
+    let raw = std::fs::read(&path).unwrap();
+    std::fs::write(&path, &raw[..raw.len() - 10]).unwrap();
+    let ro = Config {
+        read_only: true,
+        ..salvage()
+    };
+    let db = MicroKV::open_with(&path, Credential::password("fixture password"), ro).unwrap();
+    let lost = db.salvage_report().unwrap().lost.clone().unwrap();
+    assert_eq!(lost.namespace.as_deref(), Some("otp"));
+    assert_eq!(lost.offset + lost.len, raw.len() - 10);
+    assert_eq!(db.require::<u32>("count").unwrap(), 3);
+    assert_eq!(db.namespace("users").len().unwrap(), 2);
+    assert!(db.namespace("otp").is_empty().unwrap());
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn namespace_codecs_are_recorded_in_the_header() {
+    let key = [28u8; 32];
+    let config = || Config {
+        namespace_codecs: [("interop".to_string(), Codec::Json)].into(),
+        ..Default::default()
+    };
+    if cfg!(not(feature = "json")) {
+        assert!(matches!(
+            MicroKV::in_memory_with(Credential::key(key), config()),
+            Err(Error::CorruptStore(_))
+        ));
+        return;
+    }
+
+    let path = temp("codec");
+    let bundle = temp("codec-bundle");
+    let db = MicroKV::open_with(&path, Credential::key(key), config()).unwrap();
+    db.put("n", &1u32).unwrap();
+    db.namespace("interop")
+        .put("doc", &vec!["a".to_string(), "b".to_string()])
+        .unwrap();
+    db.save().unwrap();
+    db.backup_to(&bundle, Credential::key(key)).unwrap();
+    drop(db);
+
+    // marked v6, so releases that only know msgpack refuse it
+    let raw = std::fs::read(&path).unwrap();
+    let at = raw.windows(8).position(|w| w == b"\xa7microkv").unwrap() + 8;
+    assert_eq!(raw[at], 6);
+
+    // the header, not the caller's config, decides how values decode
+    let db = MicroKV::open(&path, Credential::key(key)).unwrap();
+    let doc: Vec<String> = db.namespace("interop").require("doc").unwrap();
+    assert_eq!(doc, vec!["a", "b"]);
+    assert_eq!(db.require::<u32>("n").unwrap(), 1);
+
+    // values are restored as encoded, so the codecs have to agree
+    let msgpack = MicroKV::in_memory(Credential::key(key)).unwrap();
+    assert!(matches!(
+        msgpack.restore_from(&bundle, Credential::key(key), OnConflict::Overwrite),
+        Err(Error::Serialization(_))
+    ));
+    let json = MicroKV::in_memory_with(Credential::key(key), config()).unwrap();
+    assert_eq!(
+        json.restore_from(&bundle, Credential::key(key), OnConflict::Overwrite)
+            .unwrap(),
+        2
+    );
+
+    let compiled = [
+        (Codec::Cbor, cfg!(feature = "cbor")),
+        (Codec::Postcard, cfg!(feature = "postcard")),
+        (Codec::Bincode, cfg!(feature = "bincode")),
+    ];
+    for (codec, _) in compiled.into_iter().filter(|(_, on)| *on) {
+        let config = Config {
+            codec,
+            ..Default::default()
+        };
+        let db = MicroKV::in_memory_with(Credential::key(key), config).unwrap();
+        db.put("pair", &(7u16, "seven".to_string())).unwrap();
+        let pair: (u16, String) = db.require("pair").unwrap();
+        assert_eq!(pair, (7, "seven".to_string()));
+    }
+
+    let _ = std::fs::remove_file(&path);
+    let _ = std::fs::remove_file(&bundle);
+}
+
+#[test]
+fn typed_namespaces_order_keys_like_their_type() {
+    let db = MicroKV::in_memory(Credential::key([29; 32])).unwrap();
+
+    let temps = db.typed_namespace::<i64, String>("temps");
+    for t in [12i64, -40, 0, 7, -3] {
+        temps.put(&t, &format!("{t}C")).unwrap();
+    }
+    let keys = temps.keys().unwrap();
+    assert_eq!(keys, vec![-40, -3, 0, 7, 12]);
+    let below_zero: Vec<i64> = temps.range(..0).unwrap().map(|r| r.unwrap().0).collect();
+    assert_eq!(below_zero, vec![-40, -3]);
+    assert_eq!(temps.last().unwrap().unwrap(), (12, "12C".to_string()));
+    assert!(temps
+        .compare_and_swap(&7, Some(&"7C".to_string()), Some(&"warm".to_string()))
+        .unwrap());
+    temps.update(&0, |v| v.map(|s| s + "!")).unwrap();
+    assert_eq!(temps.require(&0).unwrap(), "0C!");
+
+    // tuple keys sort component by component, even across a prefix and a NUL
+    let events = db.typed_namespace::<(String, u32), u64>("events");
+    for (user, seq) in [("bob", 2u32), ("al", 9), ("bob", 1), ("al\0x", 0), ("", 5)] {
+        events
+            .put(&(user.to_string(), seq), &u64::from(seq))
+            .unwrap();
+    }
+    let order: Vec<(String, u32)> = events.keys().unwrap();
+    let expected = [("", 5u32), ("al", 9), ("al\0x", 0), ("bob", 1), ("bob", 2)];
+    assert_eq!(
+        order,
+        expected
+            .iter()
+            .map(|(u, n)| (u.to_string(), *n))
+            .collect::<Vec<_>>()
+    );
+    let bob = ("bob".to_string(), 0)..("bob".to_string(), u32::MAX);
+    let bobs: Vec<u64> = events
+        .range(bob)
+        .unwrap()
+        .rev()
+        .map(|r| r.unwrap().1)
+        .collect();
+    assert_eq!(bobs, vec![2, 1]);
+    let from_al: Vec<u64> = events
+        .seek(&("al".to_string(), 0))
+        .unwrap()
+        .map(|r| r.unwrap().1)
+        .collect();
+    assert_eq!(from_al, vec![9, 0, 1, 2]);
+
+    // listing keys doesn't decode values, so it works through a view with the wrong type
+    let miscast = db.typed_namespace::<i64, bool>("temps");
+    assert_eq!(miscast.keys().unwrap(), vec![-40, -3, 0, 7, 12]);
+    assert!(miscast.iter().unwrap().all(|r| r.is_err()));
+
+    // a name the key type can't decode surfaces as an error, not a panic
+    temps
+        .untyped()
+        .put("not-a-number", &"?".to_string())
+        .unwrap();
+    assert!(temps.iter().unwrap().any(|r| r.is_err()));
+}
+
+#[test]
+fn watchers_hear_of_changes_after_the_write() {
+    for encrypt_names in [false, true] {
+        let config = Config {
+            encrypt_names,
+            ..Default::default()
+        };
+        let db = MicroKV::in_memory_with(Credential::key([30; 32]), config).unwrap();
+        let users = db.namespace("users");
+
+        let alice = users.watch("alice").unwrap();
+        let all = users.watch_prefix_with_values("").unwrap();
+        users.put("alice", &1u32).unwrap();
+        users.put("bob", &2u32).unwrap();
+        users.remove("alice").unwrap();
+        db.put("alice", &3u32).unwrap(); // another namespace
+
+        let seen: Vec<_> = alice.try_iter().map(|e| e.kind).collect();
+        assert_eq!(seen, vec![EventKind::Put, EventKind::Remove]);
+        let events: Vec<_> = all.try_iter().collect();
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[1].namespace, "users");
+        assert_eq!(events[1].key, "bob");
+        assert_eq!(events[1].value::<u32>().unwrap(), Some(2));
+        assert_eq!(events[2].value::<u32>().unwrap(), None);
+
+        // plain subscriptions never carry the value
+        let bob = users.watch("bob").unwrap();
+        users.put("bob", &4u32).unwrap();
+        assert_eq!(bob.try_recv().unwrap().value::<u32>().unwrap(), None);
+        assert_eq!(all.try_recv().unwrap().value::<u32>().unwrap(), Some(4));
+
+        // transactions are heard of when they commit, and not at all if they roll back
+        let _ = db.transaction(|txn| {
+            txn.put("users", "carol", &5u32)?;
+            Err::<(), _>(Error::KeyNotFound)
+        });
+        assert!(all.try_recv().is_err());
+        db.transaction(|txn| {
+            txn.put("users", "carol", &5u32)?;
+            txn.remove("users", "bob")
+        })
+        .unwrap();
+        let kinds: Vec<_> = all.try_iter().map(|e| (e.key, e.kind)).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                ("carol".to_string(), EventKind::Put),
+                ("bob".to_string(), EventKind::Remove),
+            ]
+        );
+
+        users
+            .put_with_ttl("dave", &6u32, Duration::from_secs(0))
+            .unwrap();
+        assert_eq!(db.sweep_expired().unwrap(), 1);
+        users.clear().unwrap();
+        let kinds: Vec<_> = all.try_iter().map(|e| (e.key, e.kind)).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                ("dave".to_string(), EventKind::Put),
+                ("dave".to_string(), EventKind::Expire),
+                ("carol".to_string(), EventKind::Remove),
+            ]
+        );
+
+        // a dropped receiver just stops hearing
+        drop(all);
+        users.put("erin", &7u32).unwrap();
+    }
+}
+
+#[test]
+fn background_sweeper_drops_expired_entries() {
+    let swept = Arc::new(Mutex::new(Vec::new()));
+    let report = Arc::clone(&swept);
+    let config = Config {
+        sweep_interval: Some(Duration::from_millis(20)),
+        on_sweep: Some(Arc::new(move |n| report.lock().unwrap().push(n.unwrap()))),
+        ..Default::default()
+    };
+    let db = MicroKV::in_memory_with(Credential::key([31; 32]), config).unwrap();
+    let expiring = db.watch("otp").unwrap();
+    db.put("keep", &1u8).unwrap();
+    db.put_with_ttl("otp", &2u8, Duration::from_secs(0))
+        .unwrap();
+
+    let event = expiring.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(event.kind, EventKind::Put);
+    let event = expiring.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(event.kind, EventKind::Expire);
+    // gone from the store itself, not just hidden
+    assert_eq!(db.verify().unwrap().checked, 1);
+    // sweeps that find nothing aren't reported
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(*swept.lock().unwrap(), vec![1]);
+
+    // dropping the last handle stops (and joins) the thread
+    drop(db);
+}
+
+#[test]
+fn ttl_can_be_read_changed_and_removed() {
+    let config = Config {
+        encrypt_names: true,
+        ..Default::default()
+    };
+    let db = MicroKV::in_memory_with(Credential::key([32; 32]), config).unwrap();
+    db.put_with_ttl("session", &"abc".to_string(), Duration::from_secs(600))
+        .unwrap();
+    db.put_raw("blob", &[0, 1, 2]).unwrap();
+
+    let left = db.ttl("session").unwrap().unwrap();
+    assert!(left <= Duration::from_secs(600) && left >= Duration::from_secs(598));
+    assert_eq!(db.ttl("blob").unwrap(), None);
+    assert!(matches!(db.ttl("missing"), Err(Error::KeyNotFound)));
+
+    assert!(db.persist("session").unwrap());
+    assert_eq!(db.ttl("session").unwrap(), None);
+    assert_eq!(db.require::<String>("session").unwrap(), "abc");
+
+    // values are re-sealed as they are, raw ones included
+    assert!(db.expire("blob", Duration::from_secs(3600)).unwrap());
+    assert!(db.ttl("blob").unwrap().unwrap() > Duration::from_secs(3500));
+    assert_eq!(db.get_raw("blob").unwrap(), Some(vec![0, 1, 2]));
+
+    let past = SystemTime::now() - Duration::from_secs(60);
+    assert!(db.expire_at("session", past).unwrap());
+    assert_eq!(db.get::<String>("session").unwrap(), None);
+    // an expired key is gone for these too
+    assert!(!db.persist("session").unwrap());
+    assert!(!db.expire("missing", Duration::from_secs(1)).unwrap());
+    assert!(!db.touch("session", Duration::from_secs(1)).unwrap());
+    assert_eq!(db.sweep_expired().unwrap(), 1);
+
+    // touch only ever pushes a deadline out, and leaves keys without one alone
+    let events = db.watch_with_values("token").unwrap();
+    db.put_with_ttl("token", &"t".to_string(), Duration::from_secs(60))
+        .unwrap();
+    assert!(db.touch("token", Duration::from_secs(600)).unwrap());
+    assert!(db.ttl("token").unwrap().unwrap() > Duration::from_secs(590));
+    assert!(db.touch("token", Duration::from_secs(10)).unwrap());
+    assert!(db.ttl("token").unwrap().unwrap() > Duration::from_secs(590));
+    assert!(db.touch("blob", Duration::from_secs(10)).unwrap());
+    assert!(db.ttl("blob").unwrap().unwrap() > Duration::from_secs(3500));
+    assert!(db.persist("token").unwrap());
+    assert!(db.touch("token", Duration::from_secs(10)).unwrap());
+    assert_eq!(db.ttl("token").unwrap(), None);
+
+    // watchers see expiry changes as such, without the value; no-ops send nothing
+    let seen: Vec<(EventKind, Option<String>)> = events
+        .try_iter()
+        .map(|e| (e.kind, e.value().unwrap()))
+        .collect();
+    assert_eq!(
+        seen,
+        vec![
+            (EventKind::Put, Some("t".to_string())),
+            (EventKind::Ttl, None),
+            (EventKind::Ttl, None),
+        ]
+    );
+}
+
+#[test]
+fn autosave_timers_flush_without_further_writes() {
+    let cred = || Credential::key([33; 32]);
+    // wait for the store at `path` to hold `key` on disk
+    fn saved(path: &std::path::Path, key: &str) -> bool {
+        let read_only = Config {
+            read_only: true,
+            ..Default::default()
+        };
+        (0..100).any(|_| {
+            let found =
+                MicroKV::open_existing_with(path, Credential::key([33; 32]), read_only.clone())
+                    .is_ok_and(|db| db.contains(key).unwrap());
+            if !found {
+                thread::sleep(Duration::from_millis(50));
+            }
+            found
+        })
+    }
+
+    let path = temp("autosave_periodic");
+    let config = Config {
+        autosave: AutoSave::Periodic(Duration::from_millis(50)),
+        ..Default::default()
+    };
+    let db = MicroKV::open_with(&path, cred(), config).unwrap();
+    db.put("last", &1u8).unwrap();
+    assert!(saved(&path, "last"));
+    drop(db);
+    let _ = std::fs::remove_file(&path);
+
+    let path = temp("autosave_debounced");
+    let config = Config {
+        autosave: AutoSave::Debounced(Duration::from_millis(300)),
+        ..Default::default()
+    };
+    let db = MicroKV::open_with(&path, cred(), config).unwrap();
+    let created = db.generation();
+    for n in 0..10u8 {
+        db.put(format!("k{n}"), &n).unwrap();
+    }
+    assert!(saved(&path, "k9"));
+    // the burst was saved once
+    thread::sleep(Duration::from_millis(400));
+    assert_eq!(db.generation(), created + 1);
+    drop(db);
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn autosave_keeps_writes_that_land_mid_save() {
+    let key = [40u8; 32];
+    let backend = MemBackend::default();
+    let config = Config {
+        autosave: AutoSave::Periodic(Duration::from_millis(1)),
+        ..Default::default()
+    };
+    let db = MicroKV::create_new_backend(backend.clone(), Credential::key(key), config).unwrap();
+
+    // writers race the timer; saves fail from partway through until after they're done,
+    // and are retried once they don't
+    let writers: Vec<_> = (0..4)
+        .map(|t| {
+            let db = db.clone();
+            thread::spawn(move || {
+                for n in 0..200u32 {
+                    db.put(format!("{t}-{n}"), &n).unwrap();
+                }
+            })
+        })
+        .collect();
+    thread::sleep(Duration::from_millis(5));
+    backend.fail.store(true, Ordering::SeqCst);
+    for writer in writers {
+        writer.join().unwrap();
+    }
+    thread::sleep(Duration::from_millis(20));
+    backend.fail.store(false, Ordering::SeqCst);
+
+    // the last writes reach the backend with the store still open
+    let read_only = || Config {
+        read_only: true,
+        ..Default::default()
+    };
+    let all_saved = (0..100).any(|_| {
+        let saved =
+            MicroKV::open_existing_backend(backend.clone(), Credential::key(key), read_only())
+                .unwrap()
+                .keys()
+                .unwrap()
+                .len();
+        if saved < 800 {
+            thread::sleep(Duration::from_millis(20));
+        }
+        saved == 800
+    });
+    assert!(all_saved);
+    drop(db);
+}